@@ -1,4 +1,4 @@
-/**
+/*!
 Core command line interface (CLI) parser logic
 
 The doubletime CLI supports commands for staging, committing and querying
@@ -9,17 +9,17 @@ Commands are ordered in the same sense as "module.class.method", such that the
 first command always corresponds to the base object you're modifying or adding,
 with subsequent commands detailing what you want to do. For example
 
-```
-dt event stage --project=my_project --time=6.5
+```bash
+dt event add --project=my_project --time=6.5
 ```
 
 to stage a new event under my_project for 6.5 hours. Or,
 
-```
-dt event unstage -c 12ab382l
+```bash
+dt event unstage -i 12ab382l
 ```
 
-to unstage event with hash 12ab382l.
+to unstage event with id (or unique id prefix) 12ab382l.
 */
 
 
@@ -27,10 +27,29 @@ to unstage event with hash 12ab382l.
 pub mod cli_event;
 pub mod cli_config;
 pub mod cli_project;
+pub mod cli_log;
+pub mod cli_revert;
+pub mod cli_timer;
+pub mod cli_report;
+pub mod cli_export;
+pub mod cli_import;
+pub mod cli_storage;
+pub mod cli_migrate;
 
 use crate::cli::cli_event::EventCommand;
 use crate::cli::cli_config::ConfigCommand;
 use crate::cli::cli_project::ProjectCommand;
+use crate::cli::cli_log::LogCommand;
+use crate::cli::cli_revert::RevertCommand;
+use crate::cli::cli_report::ReportCommand;
+use crate::cli::cli_export::ExportCommand;
+use crate::cli::cli_import::ImportCommand;
+use crate::cli::cli_storage::StorageCommand;
+use crate::cli::cli_migrate::MigrateCommand;
+use crate::lock::LockMode;
+use crate::cli::cli_timer::{TimerStartCommand, TimerStopCommand, TimerPauseCommand, TimerResumeCommand, TimerCurrentCommand};
+
+use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
@@ -41,7 +60,88 @@ pub enum Command {
     /// Help message for Add.
     Event(EventCommand),
     Config(ConfigCommand),
-    Project(ProjectCommand)
+    Project(ProjectCommand),
+
+    /// Show the committed history
+    Log(LogCommand),
+
+    /// Revert a committed commit or event with a compensating commit
+    Revert(RevertCommand),
+
+    /// Start a live timer for a project
+    Start(TimerStartCommand),
+
+    /// Stop a running timer and stage the elapsed time as an event
+    Stop(TimerStopCommand),
+
+    /// Pause a running timer
+    Pause(TimerPauseCommand),
+
+    /// Resume a paused timer
+    Resume(TimerResumeCommand),
+
+    /// Show the running timers
+    Current(TimerCurrentCommand),
+
+    /// Build a timesheet report of hours per project and day
+    Report(ReportCommand),
+
+    /// Export events to other formats
+    Export(ExportCommand),
+
+    /// Import events from other formats into the staging area
+    Import(ImportCommand),
+
+    /// Manage where the data is stored
+    Storage(StorageCommand),
+
+    /// Upgrade the data written by older versions of Doubletime
+    Migrate(MigrateCommand)
+}
+
+
+impl Command {
+
+    /**
+    How the command must hold the lock on the data directory while it runs:
+    exclusively if it changes any data and shared otherwise. Commands opening
+    an editor get nothing, they lock the data themselves once it is closed.
+    */
+    pub fn data_lock(&self) -> Option<LockMode> {
+        match self {
+            Command::Event(event) => {
+                match event.command {
+                    cli_event::EventSubcommand::List(_) => { return Some(LockMode::Shared); },
+                    _ => { return Some(LockMode::Exclusive); }
+                }
+            },
+            Command::Config(config) => {
+                match config.command {
+                    Some(cli_config::ConfigSubcommand::Edit(_)) => { return None; },
+                    _ => { return Some(LockMode::Shared); }
+                }
+            },
+            Command::Project(project) => {
+                match project.command {
+                    Some(cli_project::ProjectSubcommand::Add(_)) | Some(cli_project::ProjectSubcommand::Edit(_)) => { return None; },
+                    Some(cli_project::ProjectSubcommand::Remove(_)) => { return Some(LockMode::Exclusive); },
+                    _ => { return Some(LockMode::Shared); }
+                }
+            },
+            Command::Import(import) => {
+                match &import.command {
+                    cli_import::ImportSubcommand::Csv(csv) if csv.dry_run => { return Some(LockMode::Shared); },
+                    cli_import::ImportSubcommand::Csv(_) => { return Some(LockMode::Exclusive); }
+                }
+            },
+            Command::Revert(_) | Command::Start(_) | Command::Stop(_) | Command::Pause(_) |
+            Command::Resume(_) | Command::Storage(_) => { return Some(LockMode::Exclusive); },
+            Command::Migrate(migrate) if migrate.dry_run => { return Some(LockMode::Shared); },
+            Command::Migrate(_) => { return Some(LockMode::Exclusive); },
+            Command::Log(_) | Command::Current(_) | Command::Report(_) | Command::Export(_) => { return Some(LockMode::Shared); }
+        }
+    }
+
 }
 
 
@@ -73,6 +173,14 @@ pub struct Arguments {
     */
     pub disable_safeties: bool,
 
+    #[clap(long, value_name = "DIR")]
+    /**
+    Directory holding the config, staging area, ledger and projects. Takes
+    priority over the DOUBLETIME_HOME environment variable, which itself
+    takes priority over the default location.
+    */
+    pub home: Option<PathBuf>,
+
     #[clap(subcommand)]
     pub command: Command,
 
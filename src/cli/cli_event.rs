@@ -1,4 +1,4 @@
-/// This module contains the parsing logic for the add command
+//! This module contains the parsing logic for the add command
 
 
 use clap::{Args, Subcommand};
@@ -8,10 +8,50 @@ use clap::{Args, Subcommand};
 #[derive(Debug, Args)]
 pub struct EventAddCommand {
 
-    /// The date corresponding to the event to be added
-    #[arg(short, long)]
+    /// The date corresponding to the event to be added, e.g. 21-Nov-23,
+    /// 2023-11-21, today, yesterday, fri, "last mon" or -3d
+    #[arg(short, long, allow_hyphen_values = true, conflicts_with = "from")]
     pub date: Option<String>,
 
+    /// Stage one event per day, starting at this date
+    #[arg(long, allow_hyphen_values = true, requires = "to")]
+    pub from: Option<String>,
+
+    /// Last day (inclusive) of the range started with --from
+    #[arg(long, allow_hyphen_values = true, requires = "from")]
+    pub to: Option<String>,
+
+    /// Do not stage events on Saturdays and Sundays of the range
+    #[arg(long, requires = "from")]
+    pub skip_weekends: bool,
+
+    /// Do not stage events on the holidays listed in the config
+    #[arg(long, requires = "from")]
+    pub skip_holidays: bool,
+
+    /// Treat the duration as a total to spread evenly across the days of the
+    /// range, rather than the duration of each day
+    #[arg(long, requires = "from")]
+    pub spread: bool,
+
+    /// Duration of the event, either in decimal hours (6.5) or as 1h30m
+    #[arg(short, long, visible_alias = "hours", required_unless_present = "start", conflicts_with = "start")]
+    pub time: Option<String>,
+
+    /// Clock time the event started at, e.g. 09:00. The duration is derived
+    /// from the start and end times
+    #[arg(long, requires = "end", conflicts_with = "spread")]
+    pub start: Option<String>,
+
+    /// Clock time the event ended at, e.g. 11:30
+    #[arg(long, requires = "start")]
+    pub end: Option<String>,
+
+    /// Timezone the event happened in, e.g. America/New_York (the home
+    /// timezone from the config if not provided)
+    #[arg(long)]
+    pub tz: Option<String>,
+
     /// 'Commit message' for the event
     #[arg(short, long)]
     pub message: Option<String>,
@@ -23,17 +63,40 @@ pub struct EventAddCommand {
 }
 
 #[derive(Debug, Args)]
+pub struct EventListCommand {}
+
+#[derive(Debug, Args)]
+#[command(group(clap::ArgGroup::new("target").required(true).args(["id", "all", "project"])))]
 pub struct EventUnstageCommand {
 
-    /// Commit hash to unstage
+    /// Id (or any unique prefix of it) of the staged event to unstage
+    #[arg(short, long, conflicts_with = "all")]
+    pub id: Option<String>,
+
+    /// Unstage every staged event
+    #[arg(short, long, default_value_t = false)]
+    pub all: bool,
+
+    /// Only unstage events belonging to this project
     #[arg(short, long)]
-    pub id: String
+    pub project: Option<String>
 
 }
 
 
 
-/// Config subcommands
+#[derive(Debug, Args)]
+pub struct EventCommitCommand {
+
+    /// 'Commit message' describing the commit
+    #[arg(short, long)]
+    pub message: String
+
+}
+
+
+
+/// Event subcommands
 #[derive(Debug, Subcommand)]
 pub enum EventSubcommand {
 
@@ -42,10 +105,20 @@ pub enum EventSubcommand {
     */
     Add(EventAddCommand),
 
+    /**
+    List all events in the staging area
+    */
+    List(EventListCommand),
+
     /**
     Unstage an event
     */
-    Unstage(EventUnstageCommand)
+    Unstage(EventUnstageCommand),
+
+    /**
+    Commit every staged event to the ledger
+    */
+    Commit(EventCommitCommand)
 }
 
 
@@ -0,0 +1,66 @@
+//! This module contains the parsing logic for the export command
+
+
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand, ValueEnum};
+
+
+
+/// Which events to export
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportSource {
+    Committed,
+    Staged,
+    All
+}
+
+
+#[derive(Debug, Args)]
+pub struct ExportCsvCommand {
+
+    /// Comma separated columns to export. Available columns are date, project,
+    /// hours, message, uuid, commit and meta:<key> for any project metadata key
+    #[arg(short, long, default_value = "date,project,hours,message,uuid,commit")]
+    pub columns: String,
+
+    /// Whether to export committed events, staged events or both
+    #[arg(short, long, value_enum, default_value_t = ExportSource::Committed)]
+    pub source: ExportSource,
+
+    /// Only export events belonging to this project
+    #[arg(short, long)]
+    pub project: Option<String>,
+
+    /// Only export events on or after this date
+    #[arg(long, allow_hyphen_values = true)]
+    pub from: Option<String>,
+
+    /// Only export events on or before this date
+    #[arg(long, allow_hyphen_values = true)]
+    pub to: Option<String>,
+
+    /// File to write the csv to, stdout if not provided
+    #[arg(short, long)]
+    pub output: Option<PathBuf>
+
+}
+
+
+
+/// Export subcommands
+#[derive(Debug, Subcommand)]
+pub enum ExportSubcommand {
+
+    /// Export events as csv
+    Csv(ExportCsvCommand)
+}
+
+
+#[derive(Debug, Args)]
+pub struct ExportCommand {
+
+    #[clap(subcommand)]
+    pub command: ExportSubcommand,
+
+}
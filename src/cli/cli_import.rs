@@ -0,0 +1,57 @@
+//! This module contains the parsing logic for the import command
+
+
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+
+
+#[derive(Debug, Args)]
+pub struct ImportCsvCommand {
+
+    /// Csv file to import
+    pub file: PathBuf,
+
+    /// Maps an event field (date, project, hours or message) onto a csv
+    /// column, e.g. --map hours=Duration. Can be repeated. Fields that are not
+    /// mapped are read from the column with the same name.
+    #[arg(long, value_name = "FIELD=COLUMN")]
+    pub map: Vec<String>,
+
+    /// Yaml file mapping event fields onto csv columns, e.g. `hours: Duration`
+    #[arg(long)]
+    pub mapping_file: Option<PathBuf>,
+
+    /// Format of the dates in the csv (e.g. %Y-%m-%d), detected if not given
+    #[arg(long)]
+    pub date_format: Option<String>,
+
+    /// Report what would be staged and which rows fail, without staging
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Stage the valid rows even if some rows fail
+    #[arg(long, default_value_t = false)]
+    pub skip_invalid: bool
+
+}
+
+
+
+/// Import subcommands
+#[derive(Debug, Subcommand)]
+pub enum ImportSubcommand {
+
+    /// Import events from csv into the staging area
+    Csv(ImportCsvCommand)
+}
+
+
+#[derive(Debug, Args)]
+pub struct ImportCommand {
+
+    #[clap(subcommand)]
+    pub command: ImportSubcommand,
+
+}
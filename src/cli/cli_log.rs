@@ -0,0 +1,35 @@
+//! This module contains the parsing logic for the log command
+
+
+use clap::Args;
+
+
+
+#[derive(Debug, Args)]
+pub struct LogCommand {
+
+    /// Only show events belonging to this project
+    #[arg(short, long)]
+    pub project: Option<String>,
+
+    /// Only show events on or after this date
+    #[arg(long, allow_hyphen_values = true)]
+    pub since: Option<String>,
+
+    /// Only show events on or before this date
+    #[arg(long, allow_hyphen_values = true)]
+    pub until: Option<String>,
+
+    /// Only show events whose message contains this pattern (case insensitive)
+    #[arg(long)]
+    pub grep: Option<String>,
+
+    /// Show each commit on a single line
+    #[arg(long, default_value_t = false)]
+    pub oneline: bool,
+
+    /// Limit the number of commits shown
+    #[arg(short = 'n', long)]
+    pub max_count: Option<usize>
+
+}
@@ -0,0 +1,15 @@
+//! This module contains the parsing logic for the migrate command
+
+
+use clap::Args;
+
+
+
+#[derive(Debug, Args)]
+pub struct MigrateCommand {
+
+    /// Only report what would be upgraded, without changing anything
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool
+
+}
@@ -2,10 +2,55 @@ use clap::{Args, Subcommand};
 
 
 #[derive(Debug, Args)]
-pub struct ProjectEditCommand {}
+pub struct ProjectAddCommand {
+
+    /// Name of the project to add
+    pub name: String,
+
+    /// Write the default project without opening it in the editor
+    #[arg(long, default_value_t = false)]
+    pub no_edit: bool
+
+}
 
 #[derive(Debug, Args)]
-pub struct ProjectShowCommand {}
+pub struct ProjectEditCommand {
+
+    /// Name of the project to edit
+    pub name: String
+
+}
+
+#[derive(Debug, Args)]
+pub struct ProjectShowCommand {
+
+    /// Name of the project to show
+    pub name: String
+
+}
+
+#[derive(Debug, Args)]
+pub struct ProjectListCommand {}
+
+#[derive(Debug, Args)]
+pub struct ProjectStatusCommand {
+
+    /// Name of the project to report on, all projects if not provided
+    pub name: Option<String>,
+
+    /// Also count hours that are staged but not yet committed
+    #[arg(short, long, default_value_t = false)]
+    pub staged: bool
+
+}
+
+#[derive(Debug, Args)]
+pub struct ProjectRemoveCommand {
+
+    /// Name of the project to remove
+    pub name: String
+
+}
 
 
 
@@ -13,11 +58,23 @@ pub struct ProjectShowCommand {}
 #[derive(Debug, Subcommand)]
 pub enum ProjectSubcommand {
 
-    /// Edit the core Project file
+    /// Add a new project, seeded from the default project
+    Add(ProjectAddCommand),
+
+    /// Edit a project file
     Edit(ProjectEditCommand),
 
-    /// Show the core Project file
-    Show(ProjectShowCommand)
+    /// Show a project file
+    Show(ProjectShowCommand),
+
+    /// List all projects
+    List(ProjectListCommand),
+
+    /// Remove a project
+    Remove(ProjectRemoveCommand),
+
+    /// Show the budget burn-down of one or all projects
+    Status(ProjectStatusCommand)
 }
 
 
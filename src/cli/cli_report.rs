@@ -0,0 +1,76 @@
+//! This module contains the parsing logic for the report command
+
+
+use clap::{Args, Subcommand};
+
+
+
+#[derive(Debug, Args)]
+pub struct ReportEffortCommand {
+
+    /// Report on the month containing this date, e.g. Nov-23 (this month if
+    /// not provided)
+    #[arg(short, long)]
+    pub month: Option<String>,
+
+    /// Project metadata key to group and sum the effort by, e.g. P/A
+    #[arg(short, long)]
+    pub group_by: Option<String>,
+
+    /// Timezone to count days in, e.g. America/New_York (the home timezone
+    /// from the config if not provided)
+    #[arg(long)]
+    pub tz: Option<String>,
+
+    /// Also include hours that are staged but not yet committed
+    #[arg(short, long, default_value_t = false)]
+    pub staged: bool
+
+}
+
+
+
+/// Report subcommands
+#[derive(Debug, Subcommand)]
+pub enum ReportSubcommand {
+
+    /// Percent-effort certification report for a month
+    Effort(ReportEffortCommand)
+}
+
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(group(clap::ArgGroup::new("period").args(["week", "month", "from"])))]
+pub struct ReportCommand {
+
+    #[clap(subcommand)]
+    pub command: Option<ReportSubcommand>,
+
+    /// Report on the week containing this date (this week if no date is given)
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "today")]
+    pub week: Option<String>,
+
+    /// Report on the month containing this date, e.g. Nov-23 (this month if
+    /// no date is given)
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "today")]
+    pub month: Option<String>,
+
+    /// Report on the range starting at this date
+    #[arg(long, allow_hyphen_values = true)]
+    pub from: Option<String>,
+
+    /// Report on the range ending at this date (defaults to today)
+    #[arg(long, requires = "from", allow_hyphen_values = true)]
+    pub to: Option<String>,
+
+    /// Timezone to count days in, e.g. America/New_York (the home timezone
+    /// from the config if not provided)
+    #[arg(long)]
+    pub tz: Option<String>,
+
+    /// Also include hours that are staged but not yet committed
+    #[arg(short, long, default_value_t = false)]
+    pub staged: bool
+
+}
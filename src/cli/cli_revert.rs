@@ -0,0 +1,18 @@
+//! This module contains the parsing logic for the revert command
+
+
+use clap::Args;
+
+
+
+#[derive(Debug, Args)]
+pub struct RevertCommand {
+
+    /// Id (or any unique prefix of it) of the commit or event to revert
+    pub id: String,
+
+    /// 'Commit message' for the reverting commit
+    #[arg(short, long)]
+    pub message: Option<String>
+
+}
@@ -0,0 +1,36 @@
+//! This module contains the parsing logic for the storage command
+
+
+use clap::{Args, Subcommand};
+
+use crate::storage::StorageKind;
+
+
+
+#[derive(Debug, Args)]
+pub struct StorageMigrateCommand {
+
+    /// Storage to copy all the data to and switch over to
+    #[arg(long, value_enum)]
+    pub to: StorageKind
+
+}
+
+
+
+/// Storage subcommands
+#[derive(Debug, Subcommand)]
+pub enum StorageSubcommand {
+
+    /// Copy all the data to another storage backend and switch over to it
+    Migrate(StorageMigrateCommand)
+}
+
+
+#[derive(Debug, Args)]
+pub struct StorageCommand {
+
+    #[clap(subcommand)]
+    pub command: StorageSubcommand
+
+}
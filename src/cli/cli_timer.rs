@@ -0,0 +1,48 @@
+//! This module contains the parsing logic for the live timer commands
+
+
+use clap::Args;
+
+
+
+#[derive(Debug, Args)]
+pub struct TimerStartCommand {
+
+    /// Project name to charge the time to
+    pub project: String,
+
+    /// 'Commit message' for the event produced when the timer stops
+    #[arg(short, long)]
+    pub message: Option<String>
+
+}
+
+#[derive(Debug, Args)]
+pub struct TimerStopCommand {
+
+    /// Project of the timer to stop, only needed if several timers run
+    #[arg(short, long)]
+    pub project: Option<String>
+
+}
+
+#[derive(Debug, Args)]
+pub struct TimerPauseCommand {
+
+    /// Project of the timer to pause, only needed if several timers run
+    #[arg(short, long)]
+    pub project: Option<String>
+
+}
+
+#[derive(Debug, Args)]
+pub struct TimerResumeCommand {
+
+    /// Project of the timer to resume, only needed if several timers run
+    #[arg(short, long)]
+    pub project: Option<String>
+
+}
+
+#[derive(Debug, Args)]
+pub struct TimerCurrentCommand {}
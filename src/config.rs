@@ -1,25 +1,82 @@
-/**
+/*!
 Module for dealing with Doubletime-specific configuration files.
 */
 
-use edit;
 use std::collections::HashMap;
 
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use std::path::{PathBuf};
-use homedir::get_my_home;
-use log::{trace, info, warn, error};
+use log::{trace, info};
 
 use crate::cli;
-use crate::file_utils::{make_directory, read_file_to_string, write_string_to_file};
+use crate::datetime::{parse_absolute_date, set_home_timezone, set_preferred_format};
+use crate::error::Error;
+use crate::file_utils::{make_directory, write_string_to_file_atomically};
+use crate::lock::lock_data_directory;
+use crate::paths::home_directory;
+use crate::schema::to_yaml;
+use crate::storage::{StorageKind, open_storage};
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Config {
-    max_hours_per_day: f32,
-    
+    /// Where events, commits, projects and the config itself are stored.
+    /// Change it with `dt storage migrate` rather than by hand
+    #[serde(default)]
+    pub storage: StorageKind,
+
+    /// Maximum number of hours that can be logged on any single day
+    pub max_hours_per_day: f32,
+
+    /// Format used to display dates, e.g. %d-%b-%y or %Y-%m-%d
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+
+    /// Home timezone days are counted in, e.g. Europe/London. The timezone
+    /// of the system is used if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    /// Day reports consider to be the first day of the week, e.g. Mon or Sun
+    #[serde(default = "default_week_start")]
+    pub week_start: String,
+
+    /// Days off, e.g. 25-Dec-23 or 2023-12-25, which multi-day events can skip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holidays: Vec<String>,
+
     /// Other metadata
-    metadata: HashMap<String, String>
+    pub metadata: HashMap<String, String>
+}
+
+
+impl Config {
+
+    /// Parses the configured holidays
+    pub fn holiday_dates(&self) -> Result<Vec<NaiveDate>, Error> {
+        let mut dates: Vec<NaiveDate> = Vec::new();
+        for holiday in self.holidays.iter() {
+            match parse_absolute_date(holiday) {
+                Some(date) => dates.push(date),
+                None => {
+                    return Err(Error::Config(format!("Holiday {:?} in the config is not a valid date, expected e.g. 25-Dec-23 or 2023-12-25", holiday)));
+                }
+            }
+        }
+        return Ok(dates);
+    }
+
+}
+
+
+fn default_date_format() -> String {
+    return "%d-%b-%y".to_string();
+}
+
+
+fn default_week_start() -> String {
+    return "Mon".to_string();
 }
 
 
@@ -28,7 +85,12 @@ fn get_default_config() -> Config {
     let mut example_metadata = HashMap::new();
     example_metadata.insert("my name is".to_string(), "Matt".to_string());
     let config = Config {
+        storage: StorageKind::default(),
         max_hours_per_day: 8.0,
+        date_format: default_date_format(),
+        timezone: None,
+        week_start: default_week_start(),
+        holidays: Vec::new(),
         metadata: example_metadata
     };
     trace!("Default config retrieved: {:?}", config);
@@ -37,154 +99,176 @@ fn get_default_config() -> Config {
 
 
 /**
-Gets Doubletime's home directory
+Gets the path of a file or directory within the Doubletime home directory,
+see the paths module for how it is resolved.
 */
-fn get_doubletime_home_directory() -> PathBuf {
-    let home = get_my_home().unwrap().unwrap();
-    let mut file_path = PathBuf::from(home);
-    file_path.push("Doubletime");
-    return file_path;
+fn get_doubletime_directory_path(name: &str) -> Result<PathBuf, Error> {
+    let mut path = home_directory()?;
+    path.push(name);
+    return Ok(path);
 }
 
 
-/**
-Gets the path of a directory. The path will be {HOME}/Doubletime/{name}.
-*/
-fn get_doubletime_directory_path(name: String) -> PathBuf {
-    let mut staging_path = get_doubletime_home_directory();
-    staging_path.push(name);
-    return staging_path;
+/// Gets the config path as a PathBuf object
+pub fn get_config_path() -> Result<PathBuf, Error> {
+    let file_path = get_doubletime_directory_path("config.yaml")?;
+    trace!("get_config_path: Got config path at {}", file_path.to_string_lossy());
+    return Ok(file_path);
 }
 
 
-/// Gets the config path as a PathBuf object
-fn get_config_path() -> PathBuf {
-    trace!("get_config_path()");
-    let mut file_path = get_doubletime_home_directory();
-    file_path.push("config.yaml");
-    trace!("get_config_path: Got config path at {}", file_path.to_string_lossy());
-    return file_path;
+/// Gets the staging directory, where events are held before being committed
+pub fn get_staging_directory() -> Result<PathBuf, Error> {
+    return get_doubletime_directory_path("Staging");
+}
+
+
+/// Gets the ledger directory, where committed events are stored
+pub fn get_ledger_directory() -> Result<PathBuf, Error> {
+    return get_doubletime_directory_path("Ledger");
+}
+
+
+/// Gets the project directory, where one yaml file per project is stored
+pub fn get_project_directory() -> Result<PathBuf, Error> {
+    return get_doubletime_directory_path("Projects");
+}
+
+
+/// Gets the path of the database used by the SQLite storage
+pub fn get_database_path() -> Result<PathBuf, Error> {
+    return get_doubletime_directory_path("doubletime.sqlite");
+}
+
+
+/// Gets the directory data is moved to when it is replaced, e.g. by a
+/// storage migration
+pub fn get_backup_directory() -> Result<PathBuf, Error> {
+    return get_doubletime_directory_path("Backups");
+}
+
+
+/// Gets the path of the file persisting the running timers
+pub fn get_timer_path() -> Result<PathBuf, Error> {
+    return get_doubletime_directory_path("timers.json");
+}
+
+
+/// Loads the config from storage
+pub fn load_config() -> Result<Config, Error> {
+    trace!("load_config()");
+    let config = open_storage()?.load_config()?;
+    trace!("Loaded config {:?}", config);
+    return Ok(config);
+}
+
+
+/// Applies the settings of the config that hold for the whole process: the
+/// format dates are displayed in and the home timezone days are counted in
+pub fn apply_config(config: &Config) {
+    set_preferred_format(&config.date_format);
+    set_home_timezone(&config.timezone);
 }
 
 
 /// Writes the default configuration file
-fn write_default_config() -> Result<(), Box<dyn std::error::Error>> {
+fn write_default_config() -> Result<(), Error> {
     trace!("write_default_config()");
 
     let config = get_default_config();
-    let config_path = get_config_path();
+    let config_path = get_config_path()?;
     trace!("Attempting to write default config {:?} to {:?}", config, config_path);
-    let config_as_str = serde_yaml::to_string(&config)?;
-    write_string_to_file(&config_as_str, config_path)?;
+    let config_as_str = to_yaml(&config)?;
+    write_string_to_file_atomically(&config_as_str, config_path)?;
     trace!("Wrapping up write_default_config()");
     return Ok(());
 }
 
 /**
-Setup the Doubletime home directory and all required subdirectories
+Setup the Doubletime home directory and the default config. The storage in use
+creates whatever else it needs when it is opened.
 */
-pub fn initialize() -> Result<(), Box<dyn std::error::Error>> {
+pub fn initialize() -> Result<(), Error> {
     trace!("initialize()");
 
     // Make the home directory
-    let home = get_doubletime_home_directory();
+    let home = home_directory()?;
+    trace!("Doubletime home directory is {}", home.to_string_lossy());
     make_directory(home)?;
 
-    // Make the staging directory
-    let staging_directory = get_doubletime_directory_path("Staging".to_string());
-    make_directory(staging_directory)?;
-
-    // Make the staging directory
-    let project_directory = get_doubletime_directory_path("Projects".to_string());
-    make_directory(project_directory)?;
-
     // Make the default config if it does not exist
-    let config_path = get_config_path();
+    let config_path = get_config_path()?;
     if !config_path.exists() {
         write_default_config()?;
         info!("Default config has been created!");
         info!("Edit this config with `dt config edit`");
     }
+
+    // Open the storage once so that it is ready to use
+    open_storage()?;
     return Ok(());
 }
 
-/// Edits the config file in the user's default editor.
+/// Edits the config in the user's default editor. The edited config is only
+/// saved if it is valid.
 /// See here https://docs.rs/edit/latest/edit/fn.get_editor.html for details
 /// on how the default editor is chosen
-fn edit_config() {
+fn edit_config() -> Result<(), Error> {
     trace!("edit_config()");
 
     // Get the default editor. This is mainly for debugging.
     let editor = edit::get_editor();
-    trace!("Editing config with default editor: {:?}", editor.unwrap());
-
-    // Get the config path
-    let config_path = get_config_path();
+    trace!("Editing config with default editor: {:?}", editor);
 
-    let config_before_edit = read_file_to_string(config_path.clone());
+    let storage = open_storage()?;
+    let config = storage.load_config()?;
+    let config_before_edit = serde_yaml::to_string(&config)?;
+    let config_after_edit = edit::edit_with_builder(&config_before_edit, edit::Builder::new().suffix(".yaml"))?;
 
-    // Open the editor in your default config
-    match edit::edit_file(config_path.clone()) {
-        Ok(()) => {
-            trace!("Config edited successfully");
-        }
+    let parsed: Config = match serde_yaml::from_str(&config_after_edit) {
+        Ok(parsed) => parsed,
         Err(e) => {
-            error!("Error {} editing config at {}", e, config_path.to_string_lossy());
-            panic!();
+            return Err(Error::Config(format!("Config edit failed, the config was left unchanged: {}", e)));
         }
+    };
+    if parsed.storage != config.storage {
+        return Err(Error::Config("The storage cannot be changed by editing the config, use `dt storage migrate`".to_string()));
     }
 
-    // Now try to re-load the config from disk and check that it's valid. If it
-    // is not, we write the config_before_edit back to disk, rejecting the
-    // user's changes
-    let config_after_edit = read_file_to_string(config_path.clone());
-    let parsed: Result<Config, _> = serde_yaml::from_str(&config_after_edit.unwrap());
-    match parsed {
-        Ok(_) => {},
-        Err(_) => {
-            error!("Config edit failed");
-            error!("It's possible your edited config is not valid Config OR yaml file");
-            info!("Never fear! Rolling back config to before previous edits...");
-            let _ = write_string_to_file(&config_before_edit.unwrap(), config_path);
-        }
+    // The data is only locked once the editor is closed, so the config may
+    // have been changed by another command in the meantime
+    let _lock = lock_data_directory()?;
+    if serde_json::to_value(storage.load_config()?)? != serde_json::to_value(&config)? {
+        return Err(Error::Config("The config was changed by another command while it was being edited, it was left unchanged".to_string()));
     }
+    storage.save_config(&parsed)?;
+    info!("Config edited");
+    return Ok(());
 }
 
 
-fn show_config() {
-    let config_path = get_config_path();
-    if !config_path.exists() {
-        warn!("Config path at {:?} does not exist, cannot visualize it. Try running config edit.", config_path);
-        return;
-    }
-    match read_file_to_string(config_path.clone()) {
-        Ok(config) => {
-            let parsed: Config = serde_yaml::from_str(&config).unwrap();
-            println!("{:#?}", parsed);
-            return;
-        },
-        Err(e) => {
-            error!("Error {} reading config path at {:?} to memory", e, config_path);
-            panic!();
-        }
-    }
+fn show_config() -> Result<(), Error> {
+    let config = load_config()?;
+    println!("{:#?}", config);
+    return Ok(());
 }
 
 
-pub fn config(args: &cli::cli_config::ConfigCommand) {
+pub fn config(args: &cli::cli_config::ConfigCommand) -> Result<(), Error> {
     let current_command = &args.command;
     match current_command {
-        Some(cli::cli_config::ConfigSubcommand::Edit(_)) => {edit_config();},
-        Some(cli::cli_config::ConfigSubcommand::Show(_)) => {show_config();},
+        Some(cli::cli_config::ConfigSubcommand::Edit(_)) => {edit_config()?;},
+        Some(cli::cli_config::ConfigSubcommand::Show(_)) => {show_config()?;},
         None => {
             trace!("No config option provided!");
         }
     }
+    return Ok(());
 }
 
 
 
-// pub fn write_config(destination_path: PathBuf, config: Config) -> Result<(), Box<dyn std::error::Error>>{
+// pub fn write_config(destination_path: PathBuf, config: Config) -> Result<(), Error>{
 //     trace!("Attempting to write config file {:?} to disk at {:?}", config, destination_path.to_string_lossy());
 
 //     let config_as_str = serde_yaml::to_string(&config)?;
@@ -1,49 +1,143 @@
-use chrono::{DateTime, NaiveDate, Local, Utc};
+use std::fmt::Write;
+use std::sync::OnceLock;
 
-use log;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use chrono::format::{Item, StrftimeItems};
+use chrono_tz::Tz;
 
-/// If the provided dt is special and == "TODAY", this returns
-/// a string representing the current day in the Local timezone
-fn adjust_dt(dt: &Option<String>) -> String {
+use crate::error::Error;
+
+/// Keywords understood by parse_command_line_date, used for suggestions
+const DATE_KEYWORDS: [&str; 10] = [
+    "today", "yesterday", "mon", "tue", "wed", "thu", "fri", "sat", "sun", "last"
+];
+
+/// Format dates are stored in, which keeps the century
+const STORED_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Format used to display dates, set from the config at startup
+static PREFERRED_FORMAT: OnceLock<String> = OnceLock::new();
+
+/// Timezone days are counted in, set from the config at startup
+static HOME_TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+/// If the provided dt is not provided, this returns the current day in the
+/// provided timezone
+fn adjust_dt(dt: &Option<String>, tz: Tz) -> Option<NaiveDate> {
 
     match dt {
-        Some(dt) => {return dt.to_string();},
+        Some(_) => {return None;},
         None => {
-            log::warn!("No date provided, setting automatically as TODAY from local timezone, be sure to double check this is correct!");
-            let now_local: DateTime<Local> = Local::now();
+            log::warn!("No date provided, setting automatically as TODAY in {}, be sure to double check this is correct!", tz);
+            let now_local: DateTime<Tz> = Utc::now().with_timezone(&tz);
             log::trace!("Date is set to the default TODAY: {:?}", now_local);
-            let now_as_str = now_local.format("%d-%b-%y").to_string();
-            log::trace!("Now in local timezone is {:}", now_as_str);
-            return now_as_str;
+            return Some(now_local.date_naive());
         }
     }
 }
 
-/// Matches the provided datetime string to the correct format, or
-/// panics if it's not able to.
-fn match_dt(dt: &String) -> NaiveDate {
-    match NaiveDate::parse_from_str(dt, "%d-%b-%y") {
-        Ok(date_only) => { 
-            log::trace!("Date parsed to {:?}", date_only);
-            return date_only;
-        },
+/// Finds the most recent date on or before `today` falling on the weekday
+fn most_recent_weekday(today: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let offset = today.weekday().days_since(weekday);
+    return today - Duration::days(offset as i64);
+}
 
-        // Catch every error log and exit. These are all unrecoverable
-        // and as such we panic if any error is detected
-        Err(e) => {
-            log::error!("Error parsing date with error {:?}", e);
-            log::error!("Provided datetime which caused the error was {:}", dt);
-            log::error!("Provided datetime should be in format %d-%b-%y");
-            log::error!("For example: 21-Nov-23");
-            panic!();
+/// Parses relative offsets such as -3d, +1d or -2w
+fn match_offset(dt: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let sign: i64 = match dt.chars().next()? {
+        '-' => -1,
+        '+' => 1,
+        _ => { return None; }
+    };
+    let unit_days: i64 = match dt.chars().last()? {
+        'd' => 1,
+        'w' => 7,
+        _ => { return None; }
+    };
+    let amount: i64 = dt[1..dt.len() - 1].parse().ok()?;
+
+    // Offsets too large for a date are not valid dates either
+    let days = amount.checked_mul(unit_days)?.checked_mul(sign)?;
+    return today.checked_add_signed(TimeDelta::try_days(days)?);
+}
+
+/// Parses a date written out in full, e.g. 21-Nov-23 or 2023-11-21, as
+/// found in the config and project files
+pub fn parse_absolute_date(dt: &str) -> Option<NaiveDate> {
+    for format in ["%d-%b-%y", "%Y-%m-%d"] {
+        if let Ok(date_only) = NaiveDate::parse_from_str(dt.trim(), format) {
+            return Some(date_only);
         }
     }
+    return None;
 }
 
-pub fn validate_datetime_format(dt: &String) -> bool {
-    match NaiveDate::parse_from_str(dt, "%d-%b-%y") {
-        Ok(_) => {return true;},
-        Err(_) => {return false;}
+/// Matches the provided datetime string against every supported format,
+/// relative to today
+fn match_dt(dt: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let dt = dt.trim().to_lowercase();
+
+    if let Some(date_only) = parse_absolute_date(&dt) {
+        return Some(date_only);
+    }
+
+    match dt.as_str() {
+        "today" => { return Some(today); },
+        "yesterday" => { return Some(today - Duration::days(1)); },
+        _ => {}
+    }
+
+    if let Some(date_only) = match_offset(&dt, today) {
+        return Some(date_only);
+    }
+
+    // Weekday names refer to the most recent such day, including today
+    // unless prefixed by "last"
+    let (dt, strictly_before) = match dt.strip_prefix("last ") {
+        Some(weekday) => (weekday.trim(), true),
+        None => (dt.as_str(), false)
+    };
+    let weekday: Weekday = dt.parse().ok()?;
+    let reference = if strictly_before { today - Duration::days(1) } else { today };
+    return Some(most_recent_weekday(reference, weekday));
+}
+
+/// Builds the error returned when a date cannot be parsed, with a suggestion
+/// when the input looks like a misspelled keyword
+fn date_error(dt: &str) -> Error {
+    let word = dt.trim().to_lowercase();
+    let word = word.split_whitespace().last().unwrap_or("");
+    let suggestion = DATE_KEYWORDS.iter()
+        .chain(["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"].iter())
+        .map(|k| (strsim::levenshtein(word, k), k))
+        .filter(|(distance, _)| *distance > 0 && *distance <= 2)
+        .min_by_key(|(distance, _)| *distance);
+    let hint = match suggestion {
+        Some((_, keyword)) => format!(" Did you mean {:?}?", keyword),
+        None => String::new()
+    };
+    return Error::Parse(format!(
+        "Could not parse date {:?}.{} Accepted dates look like 21-Nov-23, 2023-11-21, today, yesterday, fri, last monday or -3d",
+        dt, hint
+    ));
+}
+
+pub fn validate_datetime_format(dt: &str) -> bool {
+    return parse_absolute_date(dt).is_some();
+}
+
+
+/// Formats a date the way it is stored, e.g. in the date of an event
+pub fn format_stored_date(date: NaiveDate) -> String {
+    return date.format(STORED_DATE_FORMAT).to_string();
+}
+
+
+/// Parses a date written by format_stored_date
+pub fn parse_stored_date(value: &str) -> Result<NaiveDate, Error> {
+    match NaiveDate::parse_from_str(value, STORED_DATE_FORMAT) {
+        Ok(date) => { return Ok(date); },
+        Err(e) => { return Err(Error::Storage(format!("Stored date {:?} is not valid: {}", value, e))); }
     }
 }
 
@@ -57,9 +151,301 @@ pub fn get_current_timestamp() -> i64 {
     return timestamp;   
 }
 
-pub fn parse_command_line_date(dt: &Option<String>) -> NaiveDate {
-    let dt: String = adjust_dt(&dt);
-    let date_only: NaiveDate = match_dt(&dt);
-    return date_only;
+/// Formats a timestamp/epoch as a human readable date and time in the home
+/// timezone
+pub fn format_timestamp(timestamp: i64) -> String {
+    match DateTime::<Utc>::from_timestamp(timestamp, 0) {
+        Some(dt) => { return dt.with_timezone(&home_timezone()).format("%a %d-%b-%y %H:%M:%S %z").to_string(); },
+        None => { return timestamp.to_string(); }
+    }
+}
+
+/**
+Parses a date provided on the command line. On top of 21-Nov-23 style dates,
+ISO dates (2023-11-21), today, yesterday, weekday names (fri, last monday) and
+offsets (-3d, -1w) are accepted. When no date is provided, today is used.
+Relative dates are relative to today in the home timezone.
+*/
+pub fn parse_command_line_date(dt: &Option<String>) -> Result<NaiveDate, Error> {
+    return parse_command_line_date_in(dt, home_timezone());
+}
+
+/// Parses a date provided on the command line, with relative dates being
+/// relative to today in the provided timezone
+pub fn parse_command_line_date_in(dt: &Option<String>, tz: Tz) -> Result<NaiveDate, Error> {
+    if let Some(today) = adjust_dt(dt, tz) {
+        return Ok(today);
+    }
+    let dt = dt.as_deref().unwrap_or("");
+    let today = today_in(tz);
+    match match_dt(dt, today) {
+        Some(date_only) => {
+            log::trace!("Date {:?} parsed to {:?}", dt, date_only);
+            return Ok(date_only);
+        },
+        None => { return Err(date_error(dt)); }
+    }
+}
+
+/// Sets the format used to display dates, falling back to the default if the
+/// format is not valid. Formats that parse but cannot be written for a date,
+/// e.g. with a time in them, are not valid either.
+pub fn set_preferred_format(format: &str) {
+    let parses = !StrftimeItems::new(format).any(|item| matches!(item, Item::Error));
+    let mut sample = String::new();
+    if !parses || write!(sample, "{}", NaiveDate::default().format(format)).is_err() {
+        log::warn!("date_format {:?} in the config is not valid, using %d-%b-%y", format);
+        return;
+    }
+    let _ = PREFERRED_FORMAT.set(format.to_string());
 }
 
+/// Formats a date for display using the preferred format
+pub fn format_date(date: NaiveDate) -> String {
+    let format = PREFERRED_FORMAT.get().map_or("%d-%b-%y", |f| f.as_str());
+    return date.format(format).to_string();
+}
+
+
+/**
+Parses a duration provided on the command line into decimal hours. Both plain
+decimal hours (e.g. `6.5`) and unit-suffixed durations (e.g. `1h30m`, `2h`,
+`45m`) are accepted.
+*/
+pub fn parse_duration(duration: &str) -> Result<f32, Error> {
+    let duration = duration.trim().to_lowercase();
+    log::trace!("Parsing duration {:?}", duration);
+
+    // Plain decimal hours are the most common case
+    if let Ok(hours) = duration.parse::<f32>() {
+        if !hours.is_finite() || hours <= 0.0 {
+            return Err(Error::Parse(format!("Duration {:?} must be a positive number of hours", duration)));
+        }
+        return Ok(hours);
+    }
+
+    // Otherwise walk the string, accumulating number/unit pairs
+    let mut hours: f32 = 0.0;
+    let mut number = String::new();
+    let mut saw_unit = false;
+    for c in duration.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let value: f32 = match number.parse() {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(Error::Parse(format!("Duration {:?} is not valid, expected e.g. 6.5 or 1h30m", duration)));
+            }
+        };
+        match c {
+            'h' => { hours += value; },
+            'm' => { hours += value / 60.0; },
+            _ => {
+                return Err(Error::Parse(format!("Unknown unit {:?} in duration {:?}, expected h or m", c, duration)));
+            }
+        }
+        number.clear();
+        saw_unit = true;
+    }
+
+    if !number.is_empty() || !saw_unit || hours <= 0.0 {
+        return Err(Error::Parse(format!("Duration {:?} is not valid, expected e.g. 6.5 or 1h30m", duration)));
+    }
+    log::trace!("Duration parsed to {} hours", hours);
+    return Ok(hours);
+}
+
+
+/**
+Parses a clock time provided on the command line, e.g. `09:00`, `9:00` or
+`17:45`.
+*/
+pub fn parse_clock_time(time: &str) -> Result<NaiveTime, Error> {
+    match NaiveTime::parse_from_str(time.trim(), "%H:%M") {
+        Ok(time) => { return Ok(time); },
+        Err(_) => {
+            return Err(Error::Parse(format!("Time {:?} is not valid, expected a 24 hour clock time such as 09:00 or 17:45", time)));
+        }
+    }
+}
+
+
+/**
+Gets the duration in hours between two clock times on the same day in the
+provided timezone, which accounts for the clocks changing on that day.
+*/
+pub fn hours_between(date: NaiveDate, start: NaiveTime, end: NaiveTime, tz: Tz) -> Result<f32, Error> {
+    if end <= start {
+        return Err(Error::Validation(format!(
+            "End time {} is not after start time {}, events spanning midnight must be split in two",
+            end.format("%H:%M"), start.format("%H:%M")
+        )));
+    }
+    let start_instant = instant_on(date, start, tz)?;
+    let end_instant = instant_on(date, end, tz)?;
+    return Ok((end_instant - start_instant).num_minutes() as f32 / 60.0);
+}
+
+
+/// Parses an IANA timezone name, e.g. Europe/London or America/New_York
+pub fn parse_timezone(name: &str) -> Result<Tz, Error> {
+    match name.trim().parse::<Tz>() {
+        Ok(tz) => { return Ok(tz); },
+        Err(_) => {
+            return Err(Error::Parse(format!("Timezone {:?} is not valid, expected an IANA name such as Europe/London or America/New_York", name)));
+        }
+    }
+}
+
+/// Gets the timezone of the system, falling back to UTC if it is unknown
+fn system_timezone() -> Tz {
+    let name = iana_time_zone::get_timezone().unwrap_or_default();
+    match name.parse::<Tz>() {
+        Ok(tz) => { return tz; },
+        Err(_) => {
+            log::warn!("Could not detect the system timezone ({:?}), using UTC. Set timezone in the config", name);
+            return Tz::UTC;
+        }
+    }
+}
+
+/// Sets the home timezone from the config, falling back to the system
+/// timezone if it is not set or not valid
+pub fn set_home_timezone(name: &Option<String>) {
+    let tz = match name.as_deref().map(parse_timezone) {
+        Some(Ok(tz)) => tz,
+        Some(Err(e)) => {
+            log::warn!("{} in the config, using the system timezone", e);
+            system_timezone()
+        },
+        None => system_timezone()
+    };
+    log::trace!("Home timezone is {}", tz);
+    let _ = HOME_TIMEZONE.set(tz);
+}
+
+/// Gets the timezone days are counted in unless told otherwise
+pub fn home_timezone() -> Tz {
+    return *HOME_TIMEZONE.get_or_init(system_timezone);
+}
+
+/// Gets the current date in the provided timezone
+pub fn today_in(tz: Tz) -> NaiveDate {
+    return Utc::now().with_timezone(&tz).date_naive();
+}
+
+/**
+Gets the instant at which the clock in the provided timezone reads the date and
+time. When the clocks go back and the time happens twice, the first one is
+used. Times skipped when the clocks go forward do not exist and are an error.
+*/
+pub fn instant_on(date: NaiveDate, time: NaiveTime, tz: Tz) -> Result<DateTime<Tz>, Error> {
+    match tz.from_local_datetime(&date.and_time(time)).earliest() {
+        Some(instant) => { return Ok(instant); },
+        None => {
+            return Err(Error::Parse(format!("{} {} does not exist in {}, the clocks skip it", date, time.format("%H:%M"), tz)));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        return NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    }
+
+    #[test]
+    fn match_dt_accepts_every_supported_format() {
+        let today = date(2023, 11, 22);  // A Wednesday
+        assert_eq!(match_dt("21-Nov-23", today), Some(date(2023, 11, 21)));
+        assert_eq!(match_dt("2023-11-21", today), Some(date(2023, 11, 21)));
+        assert_eq!(match_dt(" Today ", today), Some(today));
+        assert_eq!(match_dt("yesterday", today), Some(date(2023, 11, 21)));
+        assert_eq!(match_dt("wed", today), Some(today));
+        assert_eq!(match_dt("last wed", today), Some(date(2023, 11, 15)));
+        assert_eq!(match_dt("last monday", today), Some(date(2023, 11, 20)));
+        assert_eq!(match_dt("-3d", today), Some(date(2023, 11, 19)));
+        assert_eq!(match_dt("notaday", today), None);
+        assert_eq!(match_dt("", today), None);
+    }
+
+    #[test]
+    fn parse_absolute_date_accepts_both_formats() {
+        assert_eq!(parse_absolute_date("25-Dec-23"), Some(date(2023, 12, 25)));
+        assert_eq!(parse_absolute_date(" 2070-12-25 "), Some(date(2070, 12, 25)));
+        assert_eq!(parse_absolute_date("today"), None);
+        assert_eq!(parse_absolute_date("2023-13-01"), None);
+        assert!(validate_datetime_format("2023-11-21"));
+        assert!(!validate_datetime_format("21/11/23"));
+    }
+
+    #[test]
+    fn match_offset_handles_units_and_signs() {
+        let today = date(2023, 11, 22);
+        assert_eq!(match_offset("+1d", today), Some(date(2023, 11, 23)));
+        assert_eq!(match_offset("-2w", today), Some(date(2023, 11, 8)));
+        assert_eq!(match_offset("3d", today), None);
+        assert_eq!(match_offset("-3y", today), None);
+        assert_eq!(match_offset("-d", today), None);
+    }
+
+    #[test]
+    fn match_offset_rejects_offsets_out_of_range() {
+        let today = date(2023, 11, 22);
+        assert_eq!(match_offset("-99999999999d", today), None);
+        assert_eq!(match_offset("+9223372036854775807w", today), None);
+        assert!(parse_command_line_date_in(&Some("-99999999999d".to_string()), Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn parse_duration_accepts_hours_and_units() {
+        assert_eq!(parse_duration("6.5").unwrap(), 6.5);
+        assert_eq!(parse_duration("1h30m").unwrap(), 1.5);
+        assert_eq!(parse_duration("2H").unwrap(), 2.0);
+        assert_eq!(parse_duration("45m").unwrap(), 0.75);
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid_durations() {
+        for duration in ["", "0", "-1", "nan", "inf", "1h30", "1x", "h", "0m"] {
+            assert!(parse_duration(duration).is_err(), "{:?} should be rejected", duration);
+        }
+    }
+
+    fn time(value: &str) -> NaiveTime {
+        return parse_clock_time(value).unwrap();
+    }
+
+    #[test]
+    fn hours_between_follows_the_clock_changes() {
+        let london = chrono_tz::Europe::London;
+        assert_eq!(hours_between(date(2023, 3, 25), time("00:00"), time("03:00"), london).unwrap(), 3.0);
+        // The clocks go forward at 01:00 and back at 02:00
+        assert_eq!(hours_between(date(2023, 3, 26), time("00:00"), time("03:00"), london).unwrap(), 2.0);
+        assert_eq!(hours_between(date(2023, 10, 29), time("00:00"), time("03:00"), london).unwrap(), 4.0);
+        // Times that happen twice are the first of them
+        assert_eq!(hours_between(date(2023, 10, 29), time("00:00"), time("01:30"), london).unwrap(), 1.5);
+        assert_eq!(hours_between(date(2023, 3, 26), time("00:00"), time("03:00"), Tz::UTC).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn hours_between_rejects_skipped_and_reversed_times() {
+        let london = chrono_tz::Europe::London;
+        assert!(matches!(hours_between(date(2023, 3, 26), time("01:30"), time("03:00"), london), Err(Error::Parse(_))));
+        assert!(matches!(hours_between(date(2023, 3, 26), time("00:00"), time("01:30"), london), Err(Error::Parse(_))));
+        assert!(matches!(hours_between(date(2023, 3, 26), time("09:00"), time("09:00"), london), Err(Error::Validation(_))));
+        assert!(matches!(hours_between(date(2023, 3, 26), time("23:00"), time("01:00"), london), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn preferred_format_rejects_formats_with_a_time() {
+        set_preferred_format("%Y-%m-%d %H:%M");
+        assert!(PREFERRED_FORMAT.get().is_none());
+        assert_eq!(format_date(date(2023, 11, 21)), "21-Nov-23");
+    }
+}
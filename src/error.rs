@@ -0,0 +1,107 @@
+/*!
+The errors Doubletime can run into. Every fallible operation returns an `Error`
+whose kind tells what went wrong, and which `dt` maps to its exit code:
+
+| Exit code | Error                                                       |
+|-----------|-------------------------------------------------------------|
+| 0         | Success                                                     |
+| 2         | The command line could not be parsed (reported by clap)     |
+| 3         | `Parse`: a date, duration, time, timezone or csv is invalid |
+| 4         | `Validation`: refused by a safety check or inconsistent     |
+| 5         | `Storage`: data could not be read or written                |
+| 6         | `Config`: the config is missing or invalid                  |
+| 7         | `Project`: a project is missing, duplicated or invalid      |
+*/
+
+use std::fmt;
+
+
+#[derive(Debug)]
+pub enum Error {
+
+    /// A date, duration, clock time, timezone or csv file could not be parsed
+    Parse(String),
+
+    /// The data was refused by a safety check or is inconsistent, e.g. an
+    /// overlap or a day over the maximum hours
+    Validation(String),
+
+    /// Data could not be read from or written to storage
+    Storage(String),
+
+    /// The config is missing, invalid or cannot be changed that way
+    Config(String),
+
+    /// A project does not exist, already exists or is invalid
+    Project(String)
+}
+
+
+impl Error {
+
+    /// Exit code of `dt` when it stops on this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Parse(_) => { return 3; },
+            Error::Validation(_) => { return 4; },
+            Error::Storage(_) => { return 5; },
+            Error::Config(_) => { return 6; },
+            Error::Project(_) => { return 7; }
+        }
+    }
+
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(message) | Error::Validation(message) | Error::Storage(message) |
+            Error::Config(message) | Error::Project(message) => { return write!(f, "{}", message); }
+        }
+    }
+}
+
+
+impl std::error::Error for Error {}
+
+
+// Errors of the libraries used underneath. Where the same library error can
+// mean different things (e.g. invalid yaml in the config or in a project), the
+// caller maps it explicitly instead.
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        return Error::Storage(e.to_string());
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        return Error::Storage(format!("Stored data is invalid: {}", e));
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(e: serde_yaml::Error) -> Error {
+        return Error::Storage(format!("Stored data is invalid: {}", e));
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        return Error::Storage(format!("Database error: {}", e));
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Error {
+        return Error::Parse(format!("Csv error: {}", e));
+    }
+}
+
+impl From<chrono::ParseError> for Error {
+    fn from(e: chrono::ParseError) -> Error {
+        return Error::Parse(e.to_string());
+    }
+}
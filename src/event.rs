@@ -1,83 +1,177 @@
 use crate::cli;
-use std::fs::File;
-use std::path::PathBuf;
-use chrono::NaiveDate;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use homedir::get_my_home;
 
-use crate::cli::cli_event::{EventCommand};
-use crate::datetime::{parse_command_line_date, get_current_timestamp};
-use crate::file_utils::make_directory;
+use crate::config::{Config, load_config};
+use crate::datetime::{
+    format_date, format_stored_date, get_current_timestamp, home_timezone, hours_between, instant_on,
+    parse_clock_time, parse_command_line_date_in, parse_duration, parse_stored_date, parse_timezone
+};
+use crate::error::Error;
+use crate::ledger::{Commit, append_commit, load_committed_events};
+use crate::project::{Project, hours_used, load_projects};
+use crate::safeties::{check_max_hours_per_day, check_overlap, check_project, check_project_budget, check_project_window, enforce};
+use crate::storage::open_storage;
 
-use log;
 
-
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Event {
-    timestamp: i64,
-    message: String,
-    project: String,
-    now_str: String,
-    uuid: String
+    pub timestamp: i64,
+    pub message: String,
+    pub project: String,
+    pub now_str: String,
+    pub uuid: String,
+
+    /// Duration of the event, in hours
+    pub hours: f32,
+
+    /// If this event is a compensating entry, the uuid of the event it reverts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<String>,
+
+    /// Clock time the event started at, e.g. 09:00, if it was provided
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+
+    /// Clock time the event ended at, e.g. 11:30, if it was provided
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+
+    /// Timezone the event was logged in, e.g. Europe/London. Events logged
+    /// before timezones were recorded are assumed to be in the home timezone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    /// Offset from UTC of the timezone at the time of the event, e.g. +01:00
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<String>
 }
 
 
 impl Event {
 
+    /// Creates a brand new event with a fresh uuid and timestamp
+    pub fn new(now: NaiveDate, hours: f32, project: String, message: String) -> Event {
+
+        // Get the current stimestamp
+        let timestamp: i64 = get_current_timestamp();
+        log::trace!("Timestamp: {}", timestamp);
+
+        // Generate a uid
+        let uuid = Uuid::new_v4().to_simple().to_string();
+        log::trace!("UID: {}", uuid);
+
+        let now_str = format_stored_date(now);
+        log::trace!("Now is {} in string format", now_str);
+
+        let mut event = Event {
+            timestamp,
+            message,
+            project,
+            now_str,
+            uuid,
+            hours,
+            reverts: None,
+            start: None,
+            end: None,
+            timezone: None,
+            utc_offset: None
+        };
+        event.set_timezone(home_timezone());
+        return event;
+    }
+
     /// Logs information about the Event to the console
-    fn log_info(&self) {
+    pub fn log_info(&self) {
         let uid_slice = &self.uuid[..8];
         log::info!("Staging Event {}", uid_slice);
-        log::info!("| date        {}", self.now_str);
+        log::info!("| date        {}", self.display_date());
+        log::info!("| hours       {}", self.hours);
+        if let (Some(start), Some(end)) = (&self.start, &self.end) {
+            log::info!("| time        {}-{}", start, end);
+        }
+        if let (Some(timezone), Some(offset)) = (&self.timezone, &self.utc_offset) {
+            log::info!("| timezone    {} ({})", timezone, offset);
+        }
         log::info!("| project     {:?}", self.project);
         log::info!("| message     {:?}", self.message);
     }
 
-    fn stage(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Adds the event to the staging area
+    pub fn stage(&self) -> Result<(), Error> {
         log::trace!("Event.stage - called");
+        open_storage()?.stage_event(self)?;
+        return Ok(());
+    }
 
-        let timestamp = self.timestamp.to_string();
+    /// Parses the stored date string back into a date
+    pub fn date(&self) -> Result<NaiveDate, Error> {
+        return parse_stored_date(&self.now_str);
+    }
 
-        // This file_path is the HOME directory on the user's computer
-        let root = get_my_home().unwrap().unwrap();
-        let mut file_path = PathBuf::from(root);
-        let _ = make_directory(file_path.clone());
+    /// Formats the date for display, showing the stored string as is if it
+    /// is not a valid date
+    pub fn display_date(&self) -> String {
+        return self.date().map_or(self.now_str.clone(), format_date);
+    }
 
-        file_path.push("staging");
-        let _ = make_directory(file_path.clone());
+    /// Sets the clock times the event spans
+    pub fn set_interval(&mut self, start: NaiveTime, end: NaiveTime) {
+        self.start = Some(start.format("%H:%M").to_string());
+        self.end = Some(end.format("%H:%M").to_string());
+        self.refresh_utc_offset();
+    }
 
-        // Create a PathBuf and build the file path
-        // let mut file_path = directory.clone();
+    /// Parses the clock times the event spans, if they were provided
+    pub fn interval(&self) -> Option<(NaiveTime, NaiveTime)> {
+        let start = NaiveTime::parse_from_str(self.start.as_deref()?, "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(self.end.as_deref()?, "%H:%M").ok()?;
+        return Some((start, end));
+    }
 
-        // Check whether or not the directory itself exists
-        if !file_path.exists() {
-            log::error!("File path '{}' does not exist", file_path.to_string_lossy());
-            panic!();
-        }
+    /// Sets the timezone the event was logged in
+    pub fn set_timezone(&mut self, tz: Tz) {
+        self.timezone = Some(tz.name().to_string());
+        self.refresh_utc_offset();
+    }
 
-        // Create the full file path
-        let fname = format!("{}.json", timestamp);
-        file_path.push(fname);
-
-        // Now assert that this file does _not_ exist. This should never
-        // happen since the file name is actually the timestamp, so unless
-        // someone is purposefully doing something really stupid, this should
-        // never happen
-        if file_path.exists() {
-            log::error!("Json file {} exists! Something has gone HORRIBLY wrong!", file_path.to_string_lossy());
-            panic!();
-        }
+    /// Gets the timezone the event was logged in
+    pub fn timezone(&self) -> Tz {
+        return self.timezone.as_deref()
+            .and_then(|name| parse_timezone(name).ok())
+            .unwrap_or_else(home_timezone);
+    }
 
-        log::debug!("Event.stage is saving to {}", file_path.to_string_lossy());
+    /// Records the offset from UTC at the start of the event, or at midday
+    /// for events without clock times
+    fn refresh_utc_offset(&mut self) {
+        let time = self.interval().map_or(NaiveTime::from_hms_opt(12, 0, 0).unwrap(), |(start, _)| start);
+        self.utc_offset = self.date().and_then(|date| instant_on(date, time, self.timezone())).ok()
+            .map(|instant| instant.format("%:z").to_string());
+    }
 
-        let file_path = File::create(file_path)?;
-        serde_json::to_writer(file_path, self)?;
-        return Ok(());
+    /// Gets the instants the event starts and ends at, if it has clock times
+    pub fn instants(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let (start, end) = self.interval()?;
+        let tz = self.timezone();
+        let date = self.date().ok()?;
+        let start = instant_on(date, start, tz).ok()?;
+        let end = instant_on(date, end, tz).ok()?;
+        return Some((start.with_timezone(&Utc), end.with_timezone(&Utc)));
     }
 
-    fn _commit(&self) {
-        panic!("not implemented!");
+    /**
+    Gets the day the event falls on in the provided timezone. Events with clock
+    times are placed on the day they start on in that timezone, while events
+    without them keep the day they were logged on.
+    */
+    pub fn date_in(&self, tz: Tz) -> Result<NaiveDate, Error> {
+        match self.instants() {
+            Some((start, _)) => { return Ok(start.with_timezone(&tz).date_naive()); },
+            None => { return self.date(); }
+        }
     }
 
 }
@@ -89,20 +183,264 @@ fn handle_default_strings(message: &Option<String>) -> String {
     }
 }
 
-fn add(args: &cli::cli_event::EventAddCommand) {
+/// Loads every event currently sitting in the staging area
+pub fn load_staged_events() -> Result<Vec<Event>, Error> {
+    return open_storage()?.load_staged_events();
+}
+
+/**
+Resolves a git-style id prefix against a list of candidate ids, returning the
+index of the single matching candidate. Errors if the prefix matches nothing
+or is ambiguous.
+*/
+pub fn resolve_id_prefix(prefix: &str, ids: &[&str]) -> Result<usize, Error> {
+    let prefix = prefix.trim().to_lowercase();
+    if prefix.is_empty() {
+        return Err(Error::Parse("An empty id cannot be resolved".to_string()));
+    }
+
+    let matches: Vec<usize> = ids.iter()
+        .enumerate()
+        .filter(|(_, id)| id.starts_with(&prefix))
+        .map(|(ii, _)| ii)
+        .collect();
+
+    match matches.len() {
+        0 => { return Err(Error::Parse(format!("No id matches {:?}", prefix))); },
+        1 => { return Ok(matches[0]); },
+        _ => {
+            let candidates: Vec<&str> = matches.iter().map(|ii| &ids[*ii][..8]).collect();
+            return Err(Error::Parse(format!(
+                "Id {:?} is ambiguous, it matches {}. Use a longer prefix",
+                prefix, candidates.join(", ")
+            )));
+        }
+    }
+}
+
+/// Sums the hours of all events logged on the provided date
+fn hours_on_date(events: &[Event], now_str: &str) -> f32 {
+    return events.iter().filter(|e| e.now_str == now_str).fold(0.0, |total, e| total + e.hours);
+}
+
+/**
+Gets the events that carry clock times and still count, which excludes
+compensating entries and the events they revert. They are not narrowed down to
+the local date of the event, since events logged in another timezone can
+overlap with it while falling on another date.
+*/
+fn timed_events<'a>(events: &[&'a Event]) -> Vec<&'a Event> {
+    let reverted: Vec<&str> = events.iter().filter_map(|e| e.reverts.as_deref()).collect();
+    return events.iter()
+        .filter(|e| e.instants().is_some())
+        .filter(|e| e.reverts.is_none() && !reverted.contains(&e.uuid.as_str()))
+        .cloned()
+        .collect();
+}
+
+/**
+Runs every safety check against a new event, given the events that are
+already staged and committed.
+*/
+pub fn validate_new_event(event: &Event, staged: &[Event], committed: &[Event], projects: &[Project], config: &Config, safeties_on: bool) -> Result<(), Error> {
+
+    // Make sure time can actually be charged to the project
+    enforce(check_project(&event.project, projects), safeties_on)?;
+    let project = projects.iter().find(|p| p.name == event.project);
+    if let Some(p) = project {
+        enforce(check_project_window(p, event.date()?), safeties_on)?;
+    }
+
+    // Make sure this event does not push the day over the configured maximum,
+    // accounting for both staged and committed events
+    let existing_hours = hours_on_date(staged, &event.now_str) + hours_on_date(committed, &event.now_str);
+    enforce(check_max_hours_per_day(event.date()?, existing_hours, event.hours, config), safeties_on)?;
+
+    // Make sure this event does not overlap with another one
+    let all_events: Vec<&Event> = staged.iter().chain(committed.iter()).collect();
+    enforce(check_overlap(event, &timed_events(&all_events)), safeties_on)?;
+
+    // Going over the project allocation is allowed, but worth a warning
+    if let Some(p) = project {
+        let used = hours_used(&event.project, committed) + hours_used(&event.project, staged);
+        if let Err(reason) = check_project_budget(p, used, event.hours) {
+            log::warn!("{}", reason);
+        }
+    }
+    return Ok(());
+}
+
+/**
+Builds a new event, runs every safety check against it and, if they pass,
+writes it to the staging area.
+*/
+pub fn stage_new_event(now: NaiveDate, hours: f32, project: String, message: String, safeties_on: bool) -> Result<Event, Error> {
+    return stage_event(Event::new(now, hours, project, message), safeties_on);
+}
+
+/// Runs every safety check against an already built event and, if they pass,
+/// writes it to the staging area
+pub fn stage_event(payload: Event, safeties_on: bool) -> Result<Event, Error> {
+    let projects = load_projects()?;
+    let config = load_config()?;
+    let staged = load_staged_events()?;
+    let committed = load_committed_events()?;
+    validate_new_event(&payload, &staged, &committed, &projects, &config, safeties_on)?;
+
+    payload.log_info();
+    payload.stage()?;
+    log::info!("Staging successful");
+    return Ok(payload);
+}
+
+/// Gets the days of the inclusive range an event spans, leaving out weekends
+/// and holidays if asked to
+fn days_in_range(from: NaiveDate, to: NaiveDate, skip_weekends: bool, skip_holidays: bool, config: &Config) -> Result<Vec<NaiveDate>, Error> {
+    if from > to {
+        return Err(Error::Validation(format!("--from {} is after --to {}", format_date(from), format_date(to))));
+    }
+    let holidays = if skip_holidays { config.holiday_dates()? } else { Vec::new() };
+    let days: Vec<NaiveDate> = from.iter_days()
+        .take_while(|d| *d <= to)
+        .filter(|d| !(skip_weekends && matches!(d.weekday(), Weekday::Sat | Weekday::Sun)))
+        .filter(|d| !holidays.contains(d))
+        .collect();
+    if days.is_empty() {
+        return Err(Error::Validation(format!("No days left between {} and {} once weekends and holidays are skipped", format_date(from), format_date(to))));
+    }
+    return Ok(days);
+}
+
+/**
+Splits a total number of hours evenly across days, rounded to the minute. The
+last day absorbs the rounding so that the days always add up to the total.
+*/
+fn spread_hours(total: f32, n_days: usize) -> Vec<f32> {
+    let total_minutes = (total * 60.0).round() as i64;
+    let per_day = total_minutes / n_days as i64;
+    let mut minutes = vec![per_day; n_days];
+    minutes[n_days - 1] += total_minutes - per_day * n_days as i64;
+    return minutes.into_iter().map(|m| m as f32 / 60.0).collect();
+}
+
+/**
+Runs every safety check against a batch of events, each one accounting for
+those before it, and only stages them if they all pass. Either the whole batch
+is staged or none of it is.
+*/
+pub fn stage_events(events: Vec<Event>, safeties_on: bool) -> Result<Vec<Event>, Error> {
+    let projects = load_projects()?;
+    let config = load_config()?;
+    let committed = load_committed_events()?;
+    let mut staged = load_staged_events()?;
+    for event in events.iter() {
+        validate_new_event(event, &staged, &committed, &projects, &config, safeties_on)?;
+        staged.push(event.clone());
+    }
+
+    for event in events.iter() {
+        event.log_info();
+    }
+    stage_all(&events)?;
+    return Ok(events);
+}
+
+/**
+Stages a batch of events that already passed their checks. If one of them
+cannot be written, those written before it are unstaged again, so that either
+the whole batch is staged or none of it is.
+*/
+pub fn stage_all(events: &[Event]) -> Result<(), Error> {
+    let storage = open_storage()?;
+    for (ii, event) in events.iter().enumerate() {
+        if let Err(e) = storage.stage_event(event) {
+            for written in events[..ii].iter() {
+                if let Err(undo_error) = storage.unstage_event(&written.uuid) {
+                    log::warn!("Event {} could not be unstaged again: {}", written.uuid, undo_error);
+                }
+            }
+            return Err(e);
+        }
+    }
+    return Ok(());
+}
+
+/// Stages one event per day of a date range, all or nothing
+fn add_range(args: &cli::cli_event::EventAddCommand, from: &str, to: &str, interval: Option<(NaiveTime, NaiveTime)>, tz: Tz, safeties_on: bool) -> Result<(), Error> {
+    let config = load_config()?;
+    let from = parse_command_line_date_in(&Some(from.to_string()), tz)?;
+    let to = parse_command_line_date_in(&Some(to.to_string()), tz)?;
+    let days = days_in_range(from, to, args.skip_weekends, args.skip_holidays, &config)?;
+
+    // Clock times give the hours of each day, which can differ on the days
+    // the clocks change
+    let hours_per_day: Vec<f32> = match (interval, args.spread) {
+        (Some((start, end)), _) => days.iter().map(|day| hours_between(*day, start, end, tz)).collect::<Result<_, _>>()?,
+        (None, true) => spread_hours(parse_time_argument(args)?, days.len()),
+        (None, false) => vec![parse_time_argument(args)?; days.len()]
+    };
+
+    let message = handle_default_strings(&args.message);
+    let project = handle_default_strings(&args.project);
+    let mut events: Vec<Event> = Vec::new();
+    for (day, hours) in days.iter().zip(hours_per_day) {
+        if hours <= 0.0 {
+            return Err(Error::Validation(format!("Spreading {:.2} hours over {} days leaves nothing for {}", hours, days.len(), format_date(*day))));
+        }
+        let mut event = Event::new(*day, hours, project.clone(), message.clone());
+        event.set_timezone(tz);
+        if let Some((start, end)) = interval {
+            event.set_interval(start, end);
+        }
+        events.push(event);
+    }
+
+    let events = stage_events(events, safeties_on)?;
+    let total: f32 = events.iter().fold(0.0, |total, e| total + e.hours);
+    log::info!("Staged {} event(s) between {} and {}, {:.2} hours total", events.len(), format_date(from), format_date(to), total);
+    return Ok(());
+}
+
+/// Parses the duration provided with --time
+fn parse_time_argument(args: &cli::cli_event::EventAddCommand) -> Result<f32, Error> {
+    match &args.time {
+        Some(time) => { return parse_duration(time); },
+        None => { return Err(Error::Parse("Either --time or --start and --end must be provided".to_string())); }
+    }
+}
+
+fn add(args: &cli::cli_event::EventAddCommand, safeties_on: bool) -> Result<(), Error> {
     log::trace!("event.add called with args {:?}", args);
 
+    // Events are logged in the home timezone unless told otherwise, e.g.
+    // while traveling
+    let tz = match &args.tz {
+        Some(name) => parse_timezone(name)?,
+        None => home_timezone()
+    };
+    log::trace!("Timezone: {}", tz);
+
+    let interval = match (&args.start, &args.end) {
+        (Some(start), Some(end)) => Some((parse_clock_time(start)?, parse_clock_time(end)?)),
+        _ => None
+    };
+
+    // Multi-day events stage one event per day of the range
+    if let (Some(from), Some(to)) = (&args.from, &args.to) {
+        return add_range(args, from, to, interval, tz, safeties_on);
+    }
+
     // Parse the command line date
-    let now: NaiveDate = parse_command_line_date(&args.date);
+    let now: NaiveDate = parse_command_line_date_in(&args.date, tz)?;
     log::trace!("Parsed date from command line to {:?}", now);
 
-    // Get the current stimestamp
-    let timestamp: i64 = get_current_timestamp();
-    log::trace!("Timestamp: {}", timestamp);
-
-    // Generate a uid
-    let uuid = Uuid::new_v4().to_simple().to_string();
-    log::trace!("UID: {}", uuid);
+    // The duration is either provided directly or derived from the clock
+    // times of the event
+    let hours = match interval {
+        Some((start, end)) => hours_between(now, start, end, tz)?,
+        None => parse_time_argument(args)?
+    };
+    log::trace!("Hours: {}", hours);
 
     // Deal with message defaults
     let message = handle_default_strings(&args.message);
@@ -112,43 +450,288 @@ fn add(args: &cli::cli_event::EventAddCommand) {
     let project = handle_default_strings(&args.project);
     log::trace!("Project: {}", project);
 
-    let now_str = now.format("%d-%b-%y").to_string();
-    log::trace!("Now is {} in string format", now_str);
+    let mut payload = Event::new(now, hours, project, message);
+    payload.set_timezone(tz);
+    if let Some((start, end)) = interval {
+        payload.set_interval(start, end);
+    }
+    stage_event(payload, safeties_on)?;
+    return Ok(());
+}
+
+/// Sorts events by date and then by the time they were staged
+pub fn sort_by_date(events: Vec<Event>) -> Result<Vec<Event>, Error> {
+    let mut keyed: Vec<((NaiveDate, i64), Event)> = events.into_iter()
+        .map(|e| Ok(((e.date()?, e.timestamp), e)))
+        .collect::<Result<_, Error>>()?;
+    keyed.sort_by_key(|(key, _)| *key);
+    return Ok(keyed.into_iter().map(|(_, e)| e).collect());
+}
 
-    let payload: Event = Event {
-        timestamp: timestamp,
-        message: message,
-        project: project,
-        now_str: now_str,
-        uuid: uuid
-    };
+/// Loads every staged event, sorted by date and then by the time it was staged
+pub fn list_staged() -> Result<Vec<Event>, Error> {
+    return sort_by_date(load_staged_events()?);
+}
 
-    payload.log_info();
+/// Prints every staged event as a table, sorted by date
+fn list() -> Result<(), Error> {
+    log::trace!("event.list called");
+
+    let events = list_staged()?;
+    if events.is_empty() {
+        log::info!("No events are staged");
+        return Ok(());
+    }
+
+    println!("{:<10} {:<10} {:<20} {:>6}  MESSAGE", "ID", "DATE", "PROJECT", "HOURS");
+    for event in events.iter() {
+        println!(
+            "{:<10} {:<10} {:<20} {:>6.2}  {}",
+            &event.uuid[..8], format_date(event.date()?), event.project, event.hours, event.message
+        );
+    }
+    let total: f32 = events.iter().map(|e| e.hours).sum();
+    println!("{} staged event(s), {:.2} hours total", events.len(), total);
+    return Ok(());
+}
+
+/**
+Removes events from the staging area, either the single event matching an id
+prefix or every staged event, optionally restricted to a project. Returns the
+events that were removed.
+*/
+pub fn unstage_events(id: Option<&str>, project: Option<&str>) -> Result<Vec<Event>, Error> {
+    let storage = open_storage()?;
+    let mut staged: Vec<Event> = storage.load_staged_events()?
+        .into_iter()
+        .filter(|e| project.is_none_or(|p| e.project == p))
+        .collect();
+
+    if let Some(id) = id {
+        let ids: Vec<&str> = staged.iter().map(|e| e.uuid.as_str()).collect();
+        let index = resolve_id_prefix(id, &ids)?;
+        staged = vec![staged.swap_remove(index)];
+    }
+
+    for event in staged.iter() {
+        storage.unstage_event(&event.uuid)?;
+    }
+    return Ok(staged);
+}
+
+/// Removes events from the staging area, either by id prefix or in bulk
+fn unstage(args: &cli::cli_event::EventUnstageCommand) -> Result<(), Error> {
+    log::trace!("event.unstage called with args {:?}", args);
+
+    let removed = unstage_events(args.id.as_deref(), args.project.as_deref())?;
+    if removed.is_empty() {
+        log::info!("Nothing to unstage");
+        return Ok(());
+    }
+    for event in removed.iter() {
+        log::info!("Unstaged event {} ({}, {}, {} hours)", &event.uuid[..8], event.display_date(), event.project, event.hours);
+    }
+    return Ok(());
+}
+
+/**
+Validates the staged events as a whole against the rules in the config,
+accounting for everything that has already been committed.
+*/
+fn validate_for_commit(staged: &[Event], committed: &[Event], projects: &[Project], config: &Config, safeties_on: bool) -> Result<(), Error> {
+    let mut project_names: Vec<&str> = staged.iter().map(|e| e.project.as_str()).collect();
+    project_names.sort();
+    project_names.dedup();
+    for name in project_names {
+        enforce(check_project(name, projects), safeties_on)?;
+    }
+    for event in staged.iter() {
+        if let Some(p) = projects.iter().find(|p| p.name == event.project) {
+            enforce(check_project_window(p, event.date()?), safeties_on)?;
+        }
+    }
+
+    let mut dates: Vec<&Event> = staged.iter().collect();
+    dates.sort_by(|a, b| a.now_str.cmp(&b.now_str));
+    dates.dedup_by(|a, b| a.now_str == b.now_str);
+    for event in dates {
+        let committed_hours = hours_on_date(committed, &event.now_str);
+        let staged_hours = hours_on_date(staged, &event.now_str);
+        enforce(check_max_hours_per_day(event.date()?, committed_hours, staged_hours, config), safeties_on)?;
+    }
+
+    // Check every staged event against the committed events and the staged
+    // events before it, so that each overlapping pair is reported once
+    for (ii, event) in staged.iter().enumerate() {
+        let others: Vec<&Event> = committed.iter().chain(staged[..ii].iter()).collect();
+        enforce(check_overlap(event, &timed_events(&others)), safeties_on)?;
+    }
+    return Ok(());
+}
+
+/**
+Commits everything in the staging area to the ledger, returning the new commit
+or nothing if the staging area was empty. Everything is validated before the
+ledger is touched, so a failure leaves the staging area untouched.
+
+Events already in the ledger are left over from a commit that was interrupted
+before it could clear the staging area, they are unstaged rather than booked
+a second time.
+*/
+pub fn commit_staged(message: &str, safeties_on: bool) -> Result<Option<Commit>, Error> {
+    let storage = open_storage()?;
+    let committed = load_committed_events()?;
+    let (leftovers, staged): (Vec<Event>, Vec<Event>) = storage.load_staged_events()?
+        .into_iter()
+        .partition(|e| committed.iter().any(|c| c.uuid == e.uuid));
+    for event in leftovers.iter() {
+        log::warn!("Event {} is already committed, unstaging it", event.uuid);
+        storage.unstage_event(&event.uuid)?;
+    }
+    if staged.is_empty() {
+        return Ok(None);
+    }
+
+    let config = load_config()?;
+    let staged = sort_by_date(staged)?;
+    let projects = load_projects()?;
+    validate_for_commit(&staged, &committed, &projects, &config, safeties_on)?;
+
+    let commit = append_commit(message, staged)?;
+
+    // Only once the commit is safely in the ledger do we clear staging
+    for event in commit.events.iter() {
+        log::trace!("Unstaging committed event {}", event.uuid);
+        storage.unstage_event(&event.uuid)?;
+    }
+    return Ok(Some(commit));
 }
 
-pub fn execute(args: &cli::cli_event::EventCommand) {
+/// Commits everything in the staging area to the ledger
+fn commit(args: &cli::cli_event::EventCommitCommand, safeties_on: bool) -> Result<(), Error> {
+    log::trace!("event.commit called with args {:?}", args);
+
+    match commit_staged(&args.message, safeties_on)? {
+        Some(commit) => {
+            log::info!("Committed {} event(s) as {} {:?}", commit.events.len(), &commit.commit_id[..8], commit.message);
+        },
+        None => {
+            log::info!("Nothing to commit, the staging area is empty");
+        }
+    }
+    return Ok(());
+}
+
+pub fn execute(args: &cli::cli_event::EventCommand, safeties_on: bool) -> Result<(), Error> {
 
     // Match to the variety of event subcommands
     let current_command = &args.command;
 
     match current_command {
-        cli::cli_event::EventSubcommand::Add(event_sc) => {add(event_sc);},
-        cli::cli_event::EventSubcommand::Unstage(_event_sc) => {
-            log::debug!("Running event.unstage");
+        cli::cli_event::EventSubcommand::Add(event_sc) => {add(event_sc, safeties_on)?;},
+        cli::cli_event::EventSubcommand::List(_) => {list()?;},
+        cli::cli_event::EventSubcommand::Unstage(event_sc) => {unstage(event_sc)?;},
+        cli::cli_event::EventSubcommand::Commit(event_sc) => {commit(event_sc, safeties_on)?;}
+    }
+
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timed_event(date: NaiveDate, start: &str, end: &str, tz: Tz) -> Event {
+        let mut event = Event::new(date, 1.0, "p".to_string(), "m".to_string());
+        event.set_timezone(tz);
+        event.set_interval(parse_clock_time(start).unwrap(), parse_clock_time(end).unwrap());
+        return event;
+    }
+
+    #[test]
+    fn events_overlapping_across_local_dates_are_compared() {
+        // 15-Oct 23:00-23:30 in Los Angeles is 16-Oct 06:00-06:30 in UTC
+        let los_angeles = timed_event(NaiveDate::from_ymd_opt(2023, 10, 15).unwrap(), "23:00", "23:30", chrono_tz::America::Los_Angeles);
+        let utc = timed_event(NaiveDate::from_ymd_opt(2023, 10, 16).unwrap(), "06:00", "07:00", Tz::UTC);
+        assert!(check_overlap(&utc, &timed_events(&[&los_angeles])).is_err());
+        assert!(check_overlap(&los_angeles, &timed_events(&[&utc])).is_err());
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        return NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    }
+
+    #[test]
+    fn days_in_range_skips_weekends_and_holidays() {
+        let config = Config { holidays: vec!["2023-12-25".to_string(), "26-Dec-23".to_string()], ..Config::default() };
+        // Fri 22-Dec-23 to Wed 27-Dec-23
+        let (from, to) = (date(2023, 12, 22), date(2023, 12, 27));
+        assert_eq!(days_in_range(from, to, false, false, &config).unwrap().len(), 6);
+        assert_eq!(
+            days_in_range(from, to, true, false, &config).unwrap(),
+            vec![date(2023, 12, 22), date(2023, 12, 25), date(2023, 12, 26), date(2023, 12, 27)]
+        );
+        assert_eq!(days_in_range(from, to, true, true, &config).unwrap(), vec![date(2023, 12, 22), date(2023, 12, 27)]);
+    }
+
+    #[test]
+    fn days_in_range_refuses_empty_ranges() {
+        let config = Config::default();
+        assert!(matches!(days_in_range(date(2023, 12, 23), date(2023, 12, 24), true, false, &config), Err(Error::Validation(_))));
+        assert!(matches!(days_in_range(date(2023, 12, 24), date(2023, 12, 23), false, false, &config), Err(Error::Validation(_))));
+        assert_eq!(days_in_range(date(2023, 12, 24), date(2023, 12, 24), false, false, &config).unwrap(), vec![date(2023, 12, 24)]);
+    }
+
+    #[test]
+    fn spread_hours_sum_to_the_total() {
+        assert_eq!(spread_hours(6.0, 3), vec![2.0, 2.0, 2.0]);
+        // 100 minutes over 3 days leave 1 minute for the last day
+        assert_eq!(spread_hours(100.0 / 60.0, 3), vec![33.0 / 60.0, 33.0 / 60.0, 34.0 / 60.0]);
+        for (total, n_days) in [(10.0, 3), (7.5, 4), (0.1, 7), (40.0, 22)] {
+            let minutes: f32 = spread_hours(total, n_days).iter().map(|h| (h * 60.0).round()).sum();
+            assert_eq!(minutes, (total * 60.0).round(), "{} hours over {} days", total, n_days);
         }
     }
 
-    
-    
+    #[test]
+    fn events_in_different_timezones_overlap_by_instant() {
+        let london = chrono_tz::Europe::London;
+        let new_york = chrono_tz::America::New_York;
+        let day = date(2023, 11, 21);
+        // 09:00 in London is 04:00 in New York
+        let in_london = timed_event(day, "09:00", "10:00", london);
+        assert!(check_overlap(&timed_event(day, "09:00", "10:00", new_york), &timed_events(&[&in_london])).is_ok());
+        assert!(check_overlap(&timed_event(day, "04:30", "05:00", new_york), &timed_events(&[&in_london])).is_err());
+        assert!(check_overlap(&timed_event(day, "10:00", "11:00", london), &timed_events(&[&in_london])).is_ok());
+    }
 
-    // match payload.stage() {
-    //     Ok(()) => {
-    //         log::info!("Staging successful");
-    //     },
-    //     Err(e) => {
-    //         log::error!("Error during Event.stage - {:?}", e);
-    //         panic!();
-    //     }
-    // }
+    #[test]
+    fn overlaps_follow_the_clock_changes() {
+        let london = chrono_tz::Europe::London;
+
+        // The clocks go forward at 01:00, so 00:30-02:30 is 00:30-01:30 UTC
+        let spring = date(2023, 3, 26);
+        let in_london = timed_event(spring, "00:30", "02:30", london);
+        assert!(check_overlap(&timed_event(spring, "01:00", "01:15", Tz::UTC), &timed_events(&[&in_london])).is_err());
+        assert!(check_overlap(&timed_event(spring, "01:45", "02:00", Tz::UTC), &timed_events(&[&in_london])).is_ok());
+
+        // The clocks go back at 02:00, and 01:00-01:30 is the first of the
+        // two, 00:00-00:30 UTC
+        let autumn = date(2023, 10, 29);
+        let in_london = timed_event(autumn, "01:00", "01:30", london);
+        assert!(check_overlap(&timed_event(autumn, "00:10", "00:20", Tz::UTC), &timed_events(&[&in_london])).is_err());
+        assert!(check_overlap(&timed_event(autumn, "01:10", "01:20", Tz::UTC), &timed_events(&[&in_london])).is_ok());
+    }
 
+    #[test]
+    fn reverted_events_do_not_overlap() {
+        let date = NaiveDate::from_ymd_opt(2023, 10, 16).unwrap();
+        let original = timed_event(date, "09:00", "10:00", Tz::UTC);
+        let mut compensating = timed_event(date, "09:00", "10:00", Tz::UTC);
+        compensating.reverts = Some(original.uuid.clone());
+        let new = timed_event(date, "09:30", "10:30", Tz::UTC);
+        assert!(timed_events(&[&original, &compensating]).is_empty());
+        assert!(check_overlap(&new, &timed_events(&[&original, &compensating])).is_ok());
+    }
 }
@@ -0,0 +1,176 @@
+/*!
+Exports events to formats other tools understand, such as csv for pasting into
+spreadsheets.
+*/
+
+use std::io::Write;
+
+use chrono::NaiveDate;
+use log::{trace, info};
+
+use crate::cli;
+use crate::cli::cli_export::ExportSource;
+use crate::datetime::{parse_command_line_date, format_date};
+use crate::error::Error;
+use crate::event::{Event, load_staged_events};
+use crate::file_utils::write_string_to_file_atomically;
+use crate::ledger::load_history;
+use crate::project::{Project, load_projects};
+
+
+/// A column that can be exported
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Date,
+    Project,
+    Hours,
+    Message,
+    Uuid,
+    CommitId,
+
+    /// Value of a project metadata key
+    Metadata(String)
+}
+
+
+impl Column {
+
+    /// Parses a single column name, e.g. "hours" or "meta:P/A"
+    pub fn parse(name: &str) -> Result<Column, Error> {
+        let name = name.trim();
+        if let Some(key) = name.strip_prefix("meta:") {
+            return Ok(Column::Metadata(key.to_string()));
+        }
+        match name.to_lowercase().as_str() {
+            "date" => { return Ok(Column::Date); },
+            "project" => { return Ok(Column::Project); },
+            "hours" => { return Ok(Column::Hours); },
+            "message" => { return Ok(Column::Message); },
+            "uuid" => { return Ok(Column::Uuid); },
+            "commit" => { return Ok(Column::CommitId); },
+            _ => {
+                return Err(Error::Parse(format!(
+                    "Unknown column {:?}, expected one of date, project, hours, message, uuid, commit or meta:<key>",
+                    name
+                )));
+            }
+        }
+    }
+
+    /// Name of the column as written in the csv header
+    pub fn header(&self) -> String {
+        match self {
+            Column::Date => { return "date".to_string(); },
+            Column::Project => { return "project".to_string(); },
+            Column::Hours => { return "hours".to_string(); },
+            Column::Message => { return "message".to_string(); },
+            Column::Uuid => { return "uuid".to_string(); },
+            Column::CommitId => { return "commit".to_string(); },
+            Column::Metadata(key) => { return format!("meta:{}", key); }
+        }
+    }
+
+}
+
+
+/// Parses a comma separated list of column names
+pub fn parse_columns(columns: &str) -> Result<Vec<Column>, Error> {
+    return columns.split(',').filter(|c| !c.trim().is_empty()).map(Column::parse).collect();
+}
+
+
+/// Gets the value of a column for an event, which was committed in the
+/// commit with the provided id (None if the event is only staged)
+fn column_value(column: &Column, event: &Event, commit_id: Option<&str>, projects: &[Project]) -> Result<String, Error> {
+    match column {
+        Column::Date => { return Ok(format_date(event.date()?)); },
+        Column::Project => { return Ok(event.project.clone()); },
+        Column::Hours => { return Ok(event.hours.to_string()); },
+        Column::Message => { return Ok(event.message.clone()); },
+        Column::Uuid => { return Ok(event.uuid.clone()); },
+        Column::CommitId => { return Ok(commit_id.unwrap_or("").to_string()); },
+        Column::Metadata(key) => {
+            return Ok(projects.iter()
+                .find(|p| p.name == event.project)
+                .and_then(|p| p.metadata.get(key))
+                .cloned()
+                .unwrap_or_default());
+        }
+    }
+}
+
+
+/// An event to export, alongside the id of the commit it belongs to (None if
+/// the event is only staged)
+struct ExportRow {
+    commit_id: Option<String>,
+    event: Event
+}
+
+
+/// Loads the events to export
+fn load_rows(args: &cli::cli_export::ExportCsvCommand) -> Result<Vec<ExportRow>, Error> {
+    let mut rows: Vec<ExportRow> = Vec::new();
+    if args.source != ExportSource::Staged {
+        for commit in load_history()?.into_iter().rev() {
+            let commit_id = commit.commit_id;
+            rows.extend(commit.events.into_iter().map(|event| ExportRow { commit_id: Some(commit_id.clone()), event }));
+        }
+    }
+    if args.source != ExportSource::Committed {
+        rows.extend(load_staged_events()?.into_iter().map(|event| ExportRow { commit_id: None, event }));
+    }
+
+    let from = args.from.clone().map(|d| parse_command_line_date(&Some(d))).transpose()?;
+    let to = args.to.clone().map(|d| parse_command_line_date(&Some(d))).transpose()?;
+    let mut keyed: Vec<((NaiveDate, i64), ExportRow)> = Vec::new();
+    for row in rows.into_iter().filter(|row| args.project.as_ref().is_none_or(|p| &row.event.project == p)) {
+        let date = row.event.date()?;
+        if from.is_none_or(|d| date >= d) && to.is_none_or(|d| date <= d) {
+            keyed.push(((date, row.event.timestamp), row));
+        }
+    }
+    keyed.sort_by_key(|(key, _)| *key);
+    return Ok(keyed.into_iter().map(|(_, row)| row).collect());
+}
+
+
+fn export_csv(args: &cli::cli_export::ExportCsvCommand) -> Result<(), Error> {
+    trace!("export.csv called with args {:?}", args);
+
+    let columns = parse_columns(&args.columns)?;
+    if columns.is_empty() {
+        return Err(Error::Parse("At least one column must be exported".to_string()));
+    }
+    let rows = load_rows(args)?;
+    let projects = load_projects()?;
+
+    // The csv is built in memory so that an output file is written in one go
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(columns.iter().map(|c| c.header()))?;
+    for row in rows.iter() {
+        let values = columns.iter().map(|c| column_value(c, &row.event, row.commit_id.as_deref(), &projects)).collect::<Result<Vec<_>, _>>()?;
+        writer.write_record(values)?;
+    }
+    let content = match writer.into_inner() {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(e) => { return Err(Error::Storage(format!("Could not write the csv: {}", e))); }
+    };
+
+    match &args.output {
+        Some(path) => {
+            write_string_to_file_atomically(&content, path.clone())?;
+            info!("Exported {} event(s) to {}", rows.len(), path.to_string_lossy());
+        },
+        None => { std::io::stdout().write_all(content.as_bytes())?; }
+    }
+    return Ok(());
+}
+
+
+pub fn execute(args: &cli::cli_export::ExportCommand) -> Result<(), Error> {
+    match &args.command {
+        cli::cli_export::ExportSubcommand::Csv(export_sc) => {export_csv(export_sc)?;}
+    }
+    return Ok(());
+}
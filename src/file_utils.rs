@@ -1,4 +1,4 @@
-/**
+/*!
 Basic file utilities that are agnostic to the Doubletime code.
 */
 
@@ -7,12 +7,15 @@ use std::io::Write;
 use std::fs::File;
 use std::path::PathBuf;
 
-use std::fs::create_dir_all;
+use std::fs::{copy, create_dir_all, read_dir, remove_file, rename};
 
 use log::trace;
+use uuid::Uuid;
+
+use crate::error::Error;
 
 
-pub fn make_directory(directory: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+pub fn make_directory(directory: PathBuf) -> Result<(), Error> {
     let dstring = directory.to_string_lossy();
 
     trace!("Attempting to make directory {} including its parents", dstring);
@@ -32,9 +35,42 @@ pub fn make_directory(directory: PathBuf) -> Result<(), Box<dyn std::error::Erro
 }
 
 
-pub fn write_string_to_file(content: &str, file_path: PathBuf) -> Result<(), std::io::Error> {
-    let mut file = File::create(file_path)?;
-    file.write_all(content.as_bytes())?;
+/**
+Writes the content to a temporary file next to file_path and then renames it
+into place, so that file_path is never left partially written. The temporary
+file has a unique name, so concurrent writers never share it.
+*/
+pub fn write_string_to_file_atomically(content: &str, file_path: PathBuf) -> Result<(), std::io::Error> {
+    let mut tmp_path = file_path.clone().into_os_string();
+    tmp_path.push(format!(".{}.tmp", Uuid::new_v4().to_simple()));
+    let tmp_path = PathBuf::from(tmp_path);
+    trace!("Atomically writing {} via {}", file_path.to_string_lossy(), tmp_path.to_string_lossy());
+
+    let result = File::create(tmp_path.clone())
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            return file.sync_all();
+        })
+        .and_then(|_| rename(tmp_path.clone(), file_path));
+    if result.is_err() {
+        // Never leave a stray temporary file behind
+        let _ = remove_file(tmp_path);
+    }
+    return result;
+}
+
+
+/// Copies a file or a directory with everything in it
+pub fn copy_recursively(source: PathBuf, destination: PathBuf) -> Result<(), std::io::Error> {
+    if !source.is_dir() {
+        copy(source, destination)?;
+        return Ok(());
+    }
+    create_dir_all(destination.clone())?;
+    for entry in read_dir(source)? {
+        let entry = entry?;
+        copy_recursively(entry.path(), destination.join(entry.file_name()))?;
+    }
     return Ok(());
 }
 
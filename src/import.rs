@@ -0,0 +1,199 @@
+/*!
+Imports events from other formats, such as csv exported from a spreadsheet.
+Imported events always land in the staging area, so they can be reviewed with
+`dt event list` before being committed.
+*/
+
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use log::{trace, info, warn};
+
+use crate::cli;
+use crate::config::load_config;
+use crate::datetime::{parse_duration, format_date};
+use crate::error::Error;
+use crate::event::{Event, load_staged_events, stage_all, validate_new_event};
+use crate::file_utils::read_file_to_string;
+use crate::ledger::load_committed_events;
+use crate::project::load_projects;
+
+
+/// Event fields that can be mapped onto csv columns
+const EVENT_FIELDS: [&str; 4] = ["date", "project", "hours", "message"];
+
+/// Date formats tried, in order, when detecting the format of a csv
+const DATE_FORMATS: [&str; 10] = [
+    "%d-%b-%y", "%d-%b-%Y", "%Y-%m-%d", "%Y/%m/%d", "%m/%d/%Y",
+    "%d/%m/%Y", "%m/%d/%y", "%d/%m/%y", "%d.%m.%Y", "%b %d, %Y"
+];
+
+
+/**
+Builds the mapping of event fields onto csv column names. Every field maps to
+the column of the same name by default, which the mapping file and then the
+--map flags override.
+*/
+fn build_mapping(args: &cli::cli_import::ImportCsvCommand) -> Result<HashMap<String, String>, Error> {
+    let mut mapping: HashMap<String, String> = EVENT_FIELDS.iter()
+        .map(|f| (f.to_string(), f.to_string()))
+        .collect();
+
+    let mut overrides: Vec<(String, String)> = Vec::new();
+    if let Some(mapping_file) = &args.mapping_file {
+        let content = read_file_to_string(mapping_file.clone())?;
+        let from_file: HashMap<String, String> = serde_yaml::from_str(&content)
+            .map_err(|e| Error::Parse(format!("Mapping file {} is invalid: {}", mapping_file.to_string_lossy(), e)))?;
+        overrides.extend(from_file);
+    }
+    for pair in args.map.iter() {
+        match pair.split_once('=') {
+            Some((field, column)) => overrides.push((field.trim().to_string(), column.trim().to_string())),
+            None => { return Err(Error::Parse(format!("--map {:?} must be of the form FIELD=COLUMN", pair))); }
+        }
+    }
+
+    for (field, column) in overrides {
+        if !EVENT_FIELDS.contains(&field.as_str()) {
+            return Err(Error::Parse(format!("Cannot map unknown event field {:?}, expected one of {}", field, EVENT_FIELDS.join(", "))));
+        }
+        mapping.insert(field, column);
+    }
+    trace!("Import mapping {:?}", mapping);
+    return Ok(mapping);
+}
+
+
+/// Detects the date format used by every one of the provided dates
+fn detect_date_format(dates: &[&str]) -> Result<String, Error> {
+    let candidates: Vec<&str> = DATE_FORMATS.iter()
+        .filter(|format| dates.iter().all(|d| NaiveDate::parse_from_str(d.trim(), format).is_ok()))
+        .cloned()
+        .collect();
+
+    match candidates.first() {
+        None => {
+            return Err(Error::Parse("Could not detect the date format of the csv, provide it with --date-format".to_string()));
+        },
+        Some(format) => {
+            if candidates.len() > 1 {
+                warn!("Dates match several formats ({}), using {}. Override with --date-format", candidates.join(", "), format);
+            }
+            info!("Detected date format {}", format);
+            return Ok(format.to_string());
+        }
+    }
+}
+
+
+/// Parses a single csv row into a new event
+fn parse_row(record: &csv::StringRecord, columns: &HashMap<&str, Option<usize>>, date_format: &str) -> Result<Event, Error> {
+    let get = |field: &str| -> &str {
+        return columns[field].and_then(|ii| record.get(ii)).unwrap_or("").trim();
+    };
+
+    let now = NaiveDate::parse_from_str(get("date"), date_format)
+        .map_err(|e| Error::Parse(format!("date {:?} does not match {}: {}", get("date"), date_format, e)))?;
+    let hours = parse_duration(get("hours"))?;
+    let project = get("project");
+    if project.is_empty() {
+        return Err(Error::Parse("project is empty".to_string()));
+    }
+    let message = match get("message") {
+        "" => "NULL",
+        message => message
+    };
+    return Ok(Event::new(now, hours, project.to_string(), message.to_string()));
+}
+
+
+fn import_csv(args: &cli::cli_import::ImportCsvCommand, safeties_on: bool) -> Result<(), Error> {
+    trace!("import.csv called with args {:?}", args);
+
+    let mapping = build_mapping(args)?;
+    let mut reader = csv::Reader::from_path(&args.file)?;
+    let headers = reader.headers()?.clone();
+    let records: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>()?;
+
+    // Locate the column of every field. Only the message is optional.
+    let mut columns: HashMap<&str, Option<usize>> = HashMap::new();
+    for field in EVENT_FIELDS.iter() {
+        let column = &mapping[*field];
+        let index = headers.iter().position(|h| h.trim() == column);
+        if index.is_none() && *field != "message" {
+            return Err(Error::Parse(format!("Csv has no column {:?} for field {}, map it with --map {}=<column>", column, field, field)));
+        }
+        columns.insert(field, index);
+    }
+
+    let date_format = match &args.date_format {
+        Some(format) => format.clone(),
+        None => {
+            let dates: Vec<&str> = records.iter()
+                .filter_map(|r| columns["date"].and_then(|ii| r.get(ii)))
+                .filter(|d| !d.trim().is_empty())
+                .collect();
+            detect_date_format(&dates)?
+        }
+    };
+
+    // Validate every row against the projects and the config, accounting for
+    // the rows accepted before it
+    let projects = load_projects()?;
+    let config = load_config()?;
+    let committed = load_committed_events()?;
+    let mut staged = load_staged_events()?;
+    let mut accepted: Vec<Event> = Vec::new();
+    let mut failures: Vec<(u64, String)> = Vec::new();
+    for record in records.iter() {
+        // Quoted fields can span lines, so the line a record starts on comes
+        // from the reader rather than from its index
+        let line = record.position().map_or(0, |p| p.line());
+        let result = parse_row(record, &columns, &date_format)
+            .and_then(|event| {
+                validate_new_event(&event, &staged, &committed, &projects, &config, safeties_on)?;
+                return Ok(event);
+            });
+        match result {
+            Ok(event) => {
+                staged.push(event.clone());
+                accepted.push(event);
+            },
+            Err(e) => { failures.push((line, e.to_string())); }
+        }
+    }
+
+    if !accepted.is_empty() {
+        let verb = if args.dry_run { "Would stage" } else { "Staging" };
+        println!("{} {} event(s):", verb, accepted.len());
+        println!("{:<10} {:<20} {:>6}  MESSAGE", "DATE", "PROJECT", "HOURS");
+        for event in accepted.iter() {
+            println!("{:<10} {:<20} {:>6.2}  {}", format_date(event.date()?), event.project, event.hours, event.message);
+        }
+    }
+    if !failures.is_empty() {
+        println!("{} row(s) failed:", failures.len());
+        for (line, reason) in failures.iter() {
+            println!("line {}: {}", line, reason);
+        }
+    }
+
+    if args.dry_run {
+        info!("Dry run, nothing was staged");
+        return Ok(());
+    }
+    if !failures.is_empty() && !args.skip_invalid {
+        return Err(Error::Validation(format!("{} row(s) failed, nothing was staged. Fix them or use --skip-invalid", failures.len())));
+    }
+    stage_all(&accepted)?;
+    info!("Imported {} event(s) into the staging area, review them with `dt event list`", accepted.len());
+    return Ok(());
+}
+
+
+pub fn execute(args: &cli::cli_import::ImportCommand, safeties_on: bool) -> Result<(), Error> {
+    match &args.command {
+        cli::cli_import::ImportSubcommand::Csv(import_sc) => {import_csv(import_sc, safeties_on)?;}
+    }
+    return Ok(());
+}
@@ -0,0 +1,237 @@
+/*!
+The ledger is the immutable, committed history of events. Every commit points
+to its parent commit, while HEAD holds the id of the most recent commit, much
+like git.
+*/
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use log::{trace, debug, info};
+
+use crate::cli;
+use crate::datetime::{get_current_timestamp, format_date, format_timestamp, parse_command_line_date};
+use crate::error::Error;
+use crate::event::{Event, resolve_id_prefix};
+use crate::storage::open_storage;
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    pub commit_id: String,
+
+    /// Id of the previous commit, None for the very first commit
+    pub parent: Option<String>,
+
+    /// 'Commit message' describing the commit as a whole
+    pub message: String,
+
+    /// Timestamp/epoch at which the commit was made
+    pub timestamp: i64,
+
+    /// Events recorded by this commit
+    pub events: Vec<Event>
+}
+
+
+/// Reads the id of the most recent commit, if anything has been committed
+pub fn read_head() -> Result<Option<String>, Error> {
+    return open_storage()?.read_head();
+}
+
+
+/// Reads a single commit from the ledger
+pub fn read_commit(commit_id: &str) -> Result<Commit, Error> {
+    return open_storage()?.read_commit(commit_id);
+}
+
+
+/// Loads the full commit history, most recent commit first
+pub fn load_history() -> Result<Vec<Commit>, Error> {
+    let mut history: Vec<Commit> = Vec::new();
+    let mut next = read_head()?;
+    while let Some(commit_id) = next {
+        let commit = read_commit(&commit_id)?;
+        next = commit.parent.clone();
+        history.push(commit);
+    }
+    trace!("Loaded {} commits from the ledger", history.len());
+    return Ok(history);
+}
+
+
+/// Loads every event that has ever been committed
+pub fn load_committed_events() -> Result<Vec<Event>, Error> {
+    let history = load_history()?;
+    return Ok(history.into_iter().flat_map(|c| c.events).collect());
+}
+
+
+/**
+Appends a new commit containing the provided events to the ledger. The storage
+only moves HEAD once the commit is written, so a failure part way through never
+corrupts the history.
+*/
+pub fn append_commit(message: &str, events: Vec<Event>) -> Result<Commit, Error> {
+    let storage = open_storage()?;
+    let commit = Commit {
+        commit_id: Uuid::new_v4().to_simple().to_string(),
+        parent: storage.read_head()?,
+        message: message.to_string(),
+        timestamp: get_current_timestamp(),
+        events
+    };
+    debug!("Appending commit {} to the ledger", commit.commit_id);
+    storage.append_commit(&commit)?;
+    return Ok(commit);
+}
+
+
+/// Filters applied to the events shown by the log
+struct LogFilter {
+    project: Option<String>,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    pattern: Option<String>
+}
+
+
+impl LogFilter {
+
+    fn from_args(args: &cli::cli_log::LogCommand) -> Result<LogFilter, Error> {
+        return Ok(LogFilter {
+            project: args.project.clone(),
+            since: args.since.clone().map(|d| parse_command_line_date(&Some(d))).transpose()?,
+            until: args.until.clone().map(|d| parse_command_line_date(&Some(d))).transpose()?,
+            pattern: args.grep.as_ref().map(|p| p.to_lowercase())
+        });
+    }
+
+    fn is_active(&self) -> bool {
+        return self.project.is_some() || self.since.is_some() || self.until.is_some() || self.pattern.is_some();
+    }
+
+    /// Keeps only the events of a commit that pass all of the filters
+    fn apply<'a>(&self, commit: &'a Commit) -> Result<Vec<&'a Event>, Error> {
+        let mut events: Vec<&Event> = Vec::new();
+        for event in commit.events.iter() {
+            let date = event.date()?;
+            if self.project.as_ref().is_none_or(|p| &event.project == p)
+                && self.since.is_none_or(|d| date >= d)
+                && self.until.is_none_or(|d| date <= d)
+                && self.pattern.as_ref().is_none_or(|p| event.message.to_lowercase().contains(p)) {
+                events.push(event);
+            }
+        }
+        return Ok(events);
+    }
+
+}
+
+
+/// Prints the committed history, most recent commit first
+pub fn log(args: &cli::cli_log::LogCommand) -> Result<(), Error> {
+    trace!("ledger.log called with args {:?}", args);
+
+    let filter = LogFilter::from_args(args)?;
+    let max_count = args.max_count.unwrap_or(usize::MAX);
+
+    let mut shown = 0;
+    for commit in load_history()?.iter() {
+        if shown >= max_count {
+            break;
+        }
+
+        // When filtering, commits without any matching event are skipped
+        let events = filter.apply(commit)?;
+        if filter.is_active() && events.is_empty() {
+            continue;
+        }
+        shown += 1;
+
+        let hours: f32 = events.iter().map(|e| e.hours).sum();
+        if args.oneline {
+            println!("{} {} ({} event(s), {:.2} hours)", &commit.commit_id[..8], commit.message, events.len(), hours);
+            continue;
+        }
+
+        println!("commit {}", commit.commit_id);
+        println!("Date:   {}", format_timestamp(commit.timestamp));
+        println!();
+        println!("    {}", commit.message);
+        println!();
+        for event in events.iter() {
+            println!(
+                "    {:<10} {:<10} {:<20} {:>6.2}  {}",
+                &event.uuid[..8], format_date(event.date()?), event.project, event.hours, event.message
+            );
+        }
+        println!();
+    }
+
+    if shown == 0 {
+        info!("No commits to show");
+    }
+    return Ok(());
+}
+
+
+/// Builds the compensating entry negating the provided event
+fn negate_event(event: &Event) -> Event {
+    return Event {
+        timestamp: get_current_timestamp(),
+        message: format!("Revert {}: {}", event.uuid, event.message),
+        project: event.project.clone(),
+        now_str: event.now_str.clone(),
+        uuid: Uuid::new_v4().to_simple().to_string(),
+        hours: -event.hours,
+        reverts: Some(event.uuid.clone()),
+        start: event.start.clone(),
+        end: event.end.clone(),
+        timezone: event.timezone.clone(),
+        utc_offset: event.utc_offset.clone()
+    };
+}
+
+
+/**
+Reverts a commit (all of its events) or a single committed event by creating
+a new commit containing negating entries. The original commit is never
+touched, so the ledger remains a complete audit trail.
+*/
+pub fn revert(args: &cli::cli_revert::RevertCommand) -> Result<(), Error> {
+    trace!("ledger.revert called with args {:?}", args);
+
+    let history = load_history()?;
+    let events: Vec<&Event> = history.iter().flat_map(|c| c.events.iter()).collect();
+
+    // The id may refer to either a commit or an event
+    let mut ids: Vec<&str> = history.iter().map(|c| c.commit_id.as_str()).collect();
+    ids.extend(events.iter().map(|e| e.uuid.as_str()));
+    let index = resolve_id_prefix(&args.id, &ids)?;
+
+    let (targets, default_message): (Vec<&Event>, String) = if index < history.len() {
+        let commit = &history[index];
+        (commit.events.iter().collect(), format!("Revert commit {} \"{}\"", &commit.commit_id[..8], commit.message))
+    } else {
+        let event = events[index - history.len()];
+        (vec![event], format!("Revert event {}", &event.uuid[..8]))
+    };
+
+    // Refuse to revert compensating entries or events that were already
+    // reverted, since either would silently skew the totals
+    for target in targets.iter() {
+        if target.reverts.is_some() {
+            return Err(Error::Validation(format!("Event {} is itself a revert and cannot be reverted", &target.uuid[..8])));
+        }
+        if events.iter().any(|e| e.reverts.as_ref() == Some(&target.uuid)) {
+            return Err(Error::Validation(format!("Event {} has already been reverted", &target.uuid[..8])));
+        }
+    }
+
+    let negated: Vec<Event> = targets.iter().map(|e| negate_event(e)).collect();
+    let message = args.message.clone().unwrap_or(default_message);
+    let commit = append_commit(&message, negated)?;
+    info!("Reverted {} event(s) in commit {} {:?}", targets.len(), &commit.commit_id[..8], commit.message);
+    return Ok(());
+}
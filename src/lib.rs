@@ -0,0 +1,66 @@
+/*!
+Doubletime is a Git-like time tracker. Events are staged, then committed to an
+append-only ledger, and are charged against projects with a budget of hours.
+
+This library holds all of the logic behind the `dt` command line tool. Every
+module exposes typed operations that take plain Rust values, e.g.
+`event::stage_event`, `event::commit_staged` or `project::create_project`,
+alongside an `execute` function that only translates the clap structs of the
+`cli` module into calls to those operations.
+
+Call `init` once before anything else:
+
+```no_run
+let config = doubletime::init()?;
+let staged = doubletime::event::list_staged()?;
+println!("{} staged event(s), at most {} hours a day", staged.len(), config.max_hours_per_day);
+# Ok::<(), doubletime::Error>(())
+```
+*/
+
+// #![warn(missing_docs)]
+#![allow(clippy::needless_return)]
+
+pub mod cli;
+pub mod config;
+pub mod datetime;
+pub mod error;
+pub mod event;
+pub mod export;
+pub mod file_utils;
+pub mod import;
+pub mod ledger;
+pub mod lock;
+pub mod migrate;
+pub mod paths;
+pub mod project;
+pub mod report;
+pub mod safeties;
+pub mod schema;
+pub mod storage;
+pub mod timer;
+
+pub use crate::config::Config;
+pub use crate::error::Error;
+pub use crate::event::Event;
+pub use crate::ledger::Commit;
+pub use crate::project::Project;
+
+
+/**
+Prepares the Doubletime home directory and storage if they do not exist yet,
+then loads the config and applies its date format and home timezone. Returns
+the config.
+
+The home directory is resolved as described in the `paths` module, unless it
+was set beforehand with `paths::set_home_directory`. Operations that change the
+data should be run while holding `lock::lock_data_directory`, and reads while
+holding `lock::lock_data_directory_in(LockMode::Shared)`, so that they never
+interleave with a `dt` command running at the same time.
+*/
+pub fn init() -> Result<Config, Error> {
+    config::initialize()?;
+    let config = config::load_config()?;
+    config::apply_config(&config);
+    return Ok(config);
+}
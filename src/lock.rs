@@ -0,0 +1,99 @@
+/*!
+Advisory lock on the Doubletime home directory. Commands that change the data
+hold it exclusively for as long as they run, so that two of them running at
+once (e.g. a timer and an editor hook) wait for each other instead of
+interleaving their writes. Commands that only read hold it shared: a change
+can span several files (a commit writes the ledger and then clears the staging
+area file by file), so reading alongside it could see the same events twice.
+
+Commands that open an editor only take the lock once the editor is closed, so
+that other commands are not held up while the user is typing.
+*/
+
+use std::fs::{File, OpenOptions};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use fs2::{FileExt, lock_contended_error};
+use log::{trace, info};
+
+use crate::error::Error;
+use crate::file_utils::make_directory;
+use crate::paths::home_directory;
+
+
+/// How long to wait for another command to release the lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to check whether the lock was released
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// Exclusive hold on the data directory, released when dropped
+pub struct DataLock {
+    file: File
+}
+
+
+impl Drop for DataLock {
+    fn drop(&mut self) {
+        trace!("Releasing the data directory lock");
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+
+/// How the lock on the data directory is held
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockMode {
+    /// Reading, alongside other readers
+    Shared,
+
+    /// Changing the data, alone
+    Exclusive
+}
+
+
+/**
+Takes the lock on the data directory, waiting for another command holding it
+in a conflicting mode to finish. Errors if it is still held after a while.
+*/
+pub fn lock_data_directory_in(mode: LockMode) -> Result<DataLock, Error> {
+    let home = home_directory()?;
+    make_directory(home.clone())?;
+    let lock_path = home.join(".lock");
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+
+    let started = Instant::now();
+    let mut waiting = false;
+    loop {
+        let attempt = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&file),
+            LockMode::Exclusive => file.try_lock_exclusive()
+        };
+        match attempt {
+            Ok(()) => { break; },
+            Err(e) if e.raw_os_error() != lock_contended_error().raw_os_error() => { return Err(e.into()); },
+            Err(_) => {}
+        }
+        if !waiting {
+            info!("Waiting for another dt command to finish");
+            waiting = true;
+        }
+        if started.elapsed() > LOCK_TIMEOUT {
+            return Err(Error::Storage(format!(
+                "The data directory is still locked by another dt command after {} seconds",
+                LOCK_TIMEOUT.as_secs()
+            )));
+        }
+        sleep(LOCK_POLL_INTERVAL);
+    }
+    trace!("Locked the data directory ({:?}) with {}", mode, lock_path.to_string_lossy());
+    return Ok(DataLock { file });
+}
+
+
+/// Takes the exclusive lock on the data directory, to change the data
+pub fn lock_data_directory() -> Result<DataLock, Error> {
+    return lock_data_directory_in(LockMode::Exclusive);
+}
@@ -1,18 +1,20 @@
-// #![warn(missing_docs)]
+/*!
+The `dt` command line tool, a thin layer that parses the command line and hands
+it over to the `doubletime` library.
+*/
 
-use std::env;
+#![allow(clippy::needless_return)]
 
-pub mod cli;
-pub mod config;
-pub mod datetime;
-pub mod event;
-pub mod file_utils;
-pub mod project;
+use std::env;
+use std::process;
 
 extern crate pretty_env_logger;
-use log;
 
-use crate::config::initialize;
+use doubletime::{cli, config, event, export, import, ledger, migrate, project, report, storage, timer};
+use doubletime::Error;
+use doubletime::config::{apply_config, initialize, load_config};
+use doubletime::lock::lock_data_directory_in;
+use doubletime::paths::{resolve_home_directory, set_home_directory};
 
 
 fn throw_loggling_level_warning(args: &cli::Arguments) {
@@ -44,49 +46,107 @@ fn are_safeties_on(args: &cli::Arguments) -> bool{
     return false;
 }
 
-fn main() {
-
-    let args = cli::parse_args();  // Parse the command line arguments
-
-    // Set the logging level for the package using a hack that sets an
-    // environment variable in local scope, which is then read back by the
-    // pretty logger. This happens right away so that all other calls
-    // have their logging levels set correctly.
-    set_logging_level(&args);
+/// Runs the command, returning the first error it runs into
+fn run(args: &cli::Arguments) -> Result<(), Error> {
 
     // Initialize files if they don't exist
     // The initialization is safe in the sense that it will attempt to create
-    // the doubletime "home" directory at <HOME>/Doubletime, in addition to
+    // the doubletime "home" directory (see the paths module), in addition to
     //other auxiliary directories and files, but will safely do nothing if
     // the directory already exists.
-    match initialize() {
-        Ok(()) => {
-            log::debug!("Initialization success");
-        }
-        Err(e) => {
-            log::error!("Error {} with initialize()", e);
-            panic!();
-        }
+    set_home_directory(resolve_home_directory(args.home.as_deref())?)?;
+
+    // Commands that change the data wait for each other, and commands that
+    // read it wait for them. The lock is released when it goes out of scope
+    // at the end of the command.
+    let _lock = match args.command.data_lock() {
+        Some(mode) => Some(lock_data_directory_in(mode)?),
+        None => None
+    };
+    initialize()?;
+    log::debug!("Initialization success");
+
+    // Dates are displayed in the format preferred in the config, and days
+    // are counted in its home timezone
+    if let Ok(config) = load_config() {
+        apply_config(&config);
+    }
+
+    // Events staged by the very first versions are invisible until moved
+    if !matches!(args.command, cli::Command::Migrate(_)) {
+        migrate::warn_about_legacy_staging();
     }
 
     // Access the current core (highest level) command
     let current_command = &args.command;
-    let _safeties_on = are_safeties_on(&args); // Do something with this later
+    let safeties_on = are_safeties_on(args);
 
     // Depending on the core command, we match against the available options
     // and run that specific logic
     match current_command {
         cli::Command::Event(sc_data) => {
             log::debug!("Event subcommand data: {:?}", sc_data);
-            event::execute(&sc_data);
+            event::execute(sc_data, safeties_on)?;
         },
         cli::Command::Config(sc_data) => {
             log::debug!("Config subcommand data: {:?}", sc_data);
-            config::config(&sc_data);
+            config::config(sc_data)?;
         },
         cli::Command::Project(sc_data) => {
             log::debug!("Project subcommand data: {:?}", sc_data);
-            project::execute(&sc_data);
+            project::execute(sc_data, safeties_on)?;
+        },
+        cli::Command::Log(sc_data) => {
+            log::debug!("Log subcommand data: {:?}", sc_data);
+            ledger::log(sc_data)?;
+        },
+        cli::Command::Revert(sc_data) => {
+            log::debug!("Revert subcommand data: {:?}", sc_data);
+            ledger::revert(sc_data)?;
+        },
+        cli::Command::Start(_) | cli::Command::Stop(_) | cli::Command::Pause(_) |
+        cli::Command::Resume(_) | cli::Command::Current(_) => {
+            log::debug!("Timer subcommand data: {:?}", current_command);
+            timer::execute(current_command, safeties_on)?;
+        },
+        cli::Command::Report(sc_data) => {
+            log::debug!("Report subcommand data: {:?}", sc_data);
+            report::execute(sc_data)?;
+        },
+        cli::Command::Export(sc_data) => {
+            log::debug!("Export subcommand data: {:?}", sc_data);
+            export::execute(sc_data)?;
+        },
+        cli::Command::Import(sc_data) => {
+            log::debug!("Import subcommand data: {:?}", sc_data);
+            import::execute(sc_data, safeties_on)?;
+        },
+        cli::Command::Storage(sc_data) => {
+            log::debug!("Storage subcommand data: {:?}", sc_data);
+            storage::execute(sc_data)?;
+        },
+        cli::Command::Migrate(sc_data) => {
+            log::debug!("Migrate subcommand data: {:?}", sc_data);
+            migrate::execute(sc_data)?;
         }
     }
+    return Ok(());
+}
+
+fn main() {
+
+    let args = cli::parse_args();  // Parse the command line arguments
+
+    // Set the logging level for the package using a hack that sets an
+    // environment variable in local scope, which is then read back by the
+    // pretty logger. This happens right away so that all other calls
+    // have their logging levels set correctly.
+    set_logging_level(&args);
+
+    // Errors are reported as a single line, and the exit code tells scripts
+    // what kind of error it was (see the error module)
+    if let Err(e) = run(&args) {
+        log::error!("{}", e);
+        process::exit(e.exit_code());
+    }
 }
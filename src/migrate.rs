@@ -0,0 +1,220 @@
+/*!
+Upgrade of the data written by older versions of Doubletime to the current
+schema version (see the schema module), with `dt migrate`.
+
+Everything in the home directory is copied to the backup directory before
+anything is rewritten. Events staged by the very first versions, which were
+kept in `$HOME/staging` rather than in the home directory, are moved into the
+staging area along the way.
+*/
+
+use std::collections::HashSet;
+use std::fs::{read_dir, remove_dir_all, rename};
+use std::path::PathBuf;
+
+use log::{trace, debug, info, warn};
+
+use crate::cli;
+use crate::config::{get_backup_directory, get_config_path, get_staging_directory};
+use crate::error::Error;
+use crate::event::Event;
+use crate::file_utils::{copy_recursively, read_file_to_string};
+use crate::paths::{home_directory, legacy_staging_directory};
+use crate::schema::{Record, SCHEMA_VERSION, from_json, yaml_version};
+use crate::storage::{Storage, StorageKind, new_backup_directory, open_storage, storage_kind, write_sqlite_config_pointer};
+use crate::timer::upgrade_timer_file;
+
+
+/// What `dt migrate` has to do to bring the data up to date
+#[derive(Debug, Default)]
+struct PendingUpgrades {
+    /// Staged events, commits, projects and config in the storage backend
+    records: usize,
+
+    /// Whether the timer file is out of date
+    timers: bool,
+
+    /// Whether the config file pointing to the SQLite storage is out of date
+    config_pointer: bool,
+
+    /// Event files left in the legacy staging directory
+    legacy_events: Vec<PathBuf>
+}
+
+
+impl PendingUpgrades {
+
+    fn is_empty(&self) -> bool {
+        return self.records == 0 && !self.timers && !self.config_pointer && self.legacy_events.is_empty();
+    }
+
+}
+
+
+/// Finds the event files staged in the legacy staging directory. The
+/// directory is ignored if it happens to be the staging area itself, e.g.
+/// with `--home $HOME` on a case insensitive file system.
+fn legacy_staged_files() -> Result<Vec<PathBuf>, Error> {
+    let legacy_directory = legacy_staging_directory()?;
+    if !legacy_directory.is_dir() {
+        return Ok(Vec::new());
+    }
+    if legacy_directory.canonicalize()? == get_staging_directory()?.canonicalize().unwrap_or_default() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in read_dir(legacy_directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    return Ok(paths);
+}
+
+
+/// Warns if events are still sitting in the legacy staging directory, where
+/// nothing reads them anymore
+pub fn warn_about_legacy_staging() {
+    match legacy_staged_files() {
+        Ok(paths) if !paths.is_empty() => {
+            warn!("{} event(s) are staged in the legacy directory {}, run `dt migrate` to move them", paths.len(),
+                legacy_staging_directory().unwrap_or_default().to_string_lossy());
+        },
+        Ok(_) => {},
+        Err(e) => { trace!("Could not look for legacy staged events: {}", e); }
+    }
+}
+
+
+/// Whether the config file of the SQLite storage predates schema versions
+fn config_pointer_is_outdated() -> Result<bool, Error> {
+    if storage_kind()? != StorageKind::Sqlite {
+        return Ok(false);
+    }
+    let content = read_file_to_string(get_config_path()?)?;
+    return Ok(yaml_version(&content)? < SCHEMA_VERSION);
+}
+
+
+/// Looks for everything that needs to be upgraded, without changing anything
+fn find_pending_upgrades(storage: &dyn Storage) -> Result<PendingUpgrades, Error> {
+    return Ok(PendingUpgrades {
+        records: storage.upgrade(true)?,
+        timers: upgrade_timer_file(true)?,
+        config_pointer: config_pointer_is_outdated()?,
+        legacy_events: legacy_staged_files()?
+    });
+}
+
+
+/**
+Copies everything in the home directory to a new backup directory, except the
+earlier backups and the lock file, returning the backup directory.
+*/
+fn back_up_home_directory() -> Result<PathBuf, Error> {
+    let home = home_directory()?;
+    let backup_directory = new_backup_directory("migrate")?;
+    let skipped = [get_backup_directory()?, home.join(".lock")];
+    for entry in read_dir(&home)? {
+        let path = entry?.path();
+        if skipped.contains(&path) {
+            continue;
+        }
+        let destination = backup_directory.join(path.file_name().unwrap_or_default());
+        trace!("Backing up {} to {}", path.to_string_lossy(), destination.to_string_lossy());
+        copy_recursively(path, destination)?;
+    }
+    info!("Data backed up to {}", backup_directory.to_string_lossy());
+    return Ok(backup_directory);
+}
+
+
+/**
+Stages the events of the legacy staging directory in the current storage,
+skipping those that are already staged, and moves the directory into the
+backup. Returns the number of events staged.
+*/
+fn import_legacy_staging(storage: &dyn Storage, paths: &[PathBuf], backup_directory: PathBuf) -> Result<usize, Error> {
+    let mut events: Vec<Event> = Vec::new();
+    for path in paths.iter() {
+        match from_json::<Event>(&read_file_to_string(path.clone())?, Record::Event) {
+            Ok(event) => { events.push(event); },
+            Err(e) => {
+                return Err(Error::Storage(format!("Legacy staged event {} is invalid: {}", path.to_string_lossy(), e)));
+            }
+        }
+    }
+
+    let staged: HashSet<String> = storage.load_staged_events()?.into_iter().map(|e| e.uuid).collect();
+    let mut n_staged = 0;
+    for event in events.iter() {
+        if staged.contains(&event.uuid) {
+            debug!("Event {} is already staged, skipping it", event.uuid);
+            continue;
+        }
+        storage.stage_event(event)?;
+        n_staged += 1;
+    }
+
+    // The directory is kept with the backup rather than deleted. Renaming
+    // fails across file systems, in which case it is copied instead.
+    let legacy_directory = legacy_staging_directory()?;
+    let destination = backup_directory.join("legacy-staging");
+    if rename(&legacy_directory, &destination).is_err() {
+        copy_recursively(legacy_directory.clone(), destination.clone())?;
+        remove_dir_all(&legacy_directory)?;
+    }
+    info!("Legacy staging directory {} moved to {}", legacy_directory.to_string_lossy(), destination.to_string_lossy());
+    return Ok(n_staged);
+}
+
+
+/**
+Rewrites everything written by an older version of Doubletime in the current
+schema version, after backing it all up, and moves the events of the legacy
+staging directory into the staging area.
+*/
+fn migrate(args: &cli::cli_migrate::MigrateCommand) -> Result<(), Error> {
+    let storage = open_storage()?;
+    let pending = find_pending_upgrades(storage.as_ref())?;
+    if pending.is_empty() {
+        info!("Everything is already at schema version {}", SCHEMA_VERSION);
+        return Ok(());
+    }
+
+    if args.dry_run {
+        info!("{} record(s) would be upgraded to schema version {}", pending.records, SCHEMA_VERSION);
+        if pending.timers {
+            info!("The timer file would be upgraded");
+        }
+        if pending.config_pointer {
+            info!("The config file would be upgraded");
+        }
+        if !pending.legacy_events.is_empty() {
+            info!("{} event(s) would be moved from the legacy staging directory", pending.legacy_events.len());
+        }
+        return Ok(());
+    }
+
+    let backup_directory = back_up_home_directory()?;
+    let n_records = storage.upgrade(false)?;
+    upgrade_timer_file(false)?;
+    if pending.config_pointer {
+        write_sqlite_config_pointer()?;
+    }
+    info!("{} record(s) upgraded to schema version {}", n_records, SCHEMA_VERSION);
+
+    if !pending.legacy_events.is_empty() {
+        let n_staged = import_legacy_staging(storage.as_ref(), &pending.legacy_events, backup_directory)?;
+        info!("{} event(s) moved from the legacy staging directory", n_staged);
+    }
+    return Ok(());
+}
+
+
+pub fn execute(args: &cli::cli_migrate::MigrateCommand) -> Result<(), Error> {
+    migrate(args)?;
+    return Ok(());
+}
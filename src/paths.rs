@@ -1,7 +1,111 @@
-use std::path::PathBuf;
-use homedir::GetHomeError;
+/*!
+Resolution of the Doubletime home directory, which holds the config, the
+staging area, the ledger and the projects. In order of priority it is:
+
+1. the directory passed with `--home`
+2. the `DOUBLETIME_HOME` environment variable
+3. `$HOME/Doubletime`, if it already exists, which is where earlier versions
+   kept everything
+4. `$XDG_DATA_HOME/doubletime`, or `$HOME/.local/share/doubletime` when
+   `XDG_DATA_HOME` is not set
+*/
+
+use std::env;
+use std::path::{Path, PathBuf, absolute};
+use std::sync::OnceLock;
+
 use homedir::get_my_home;
+use log::trace;
+
+use crate::error::Error;
+
+
+/// Home directory used by the rest of the process, once resolved
+static HOME_DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+
+/// Gets the home directory of the current user
+fn user_home() -> Result<PathBuf, Error> {
+    match get_my_home() {
+        Ok(Some(home)) if !home.as_os_str().is_empty() => { return Ok(home); },
+        Ok(_) => { return Err(Error::Config("The home directory of the current user could not be found".to_string())); },
+        Err(e) => { return Err(Error::Config(format!("The home directory of the current user could not be found: {}", e))); }
+    }
+}
+
+
+/// Reads a path from an environment variable, treating an empty value as unset
+fn path_from_env(name: &str) -> Option<PathBuf> {
+    return env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+}
+
+
+/// Makes a path absolute, so that it does not depend on the working directory
+fn make_absolute(path: &Path) -> Result<PathBuf, Error> {
+    match absolute(path) {
+        Ok(path) => { return Ok(path); },
+        Err(e) => { return Err(Error::Config(format!("Home directory {} is not valid: {}", path.to_string_lossy(), e))); }
+    }
+}
+
+
+/**
+Resolves the Doubletime home directory, from the `--home` flag if provided and
+otherwise from the environment. Nothing is created.
+*/
+pub fn resolve_home_directory(flag: Option<&Path>) -> Result<PathBuf, Error> {
+    if let Some(path) = flag {
+        trace!("Home directory {} set with --home", path.to_string_lossy());
+        return make_absolute(path);
+    }
+    if let Some(path) = path_from_env("DOUBLETIME_HOME") {
+        trace!("Home directory {} set with DOUBLETIME_HOME", path.to_string_lossy());
+        return make_absolute(&path);
+    }
+
+    // Data created by earlier versions stays where it is
+    let legacy = user_home()?.join("Doubletime");
+    if legacy.is_dir() {
+        trace!("Using the existing home directory {}", legacy.to_string_lossy());
+        return Ok(legacy);
+    }
+
+    let data_home = match path_from_env("XDG_DATA_HOME").filter(|path| path.is_absolute()) {
+        Some(path) => path,
+        None => user_home()?.join(".local").join("share")
+    };
+    trace!("Using the XDG data directory {}", data_home.to_string_lossy());
+    return Ok(data_home.join("doubletime"));
+}
+
+
+/// Gets the directory the very first versions staged events in, which
+/// `dt migrate` moves them out of
+pub fn legacy_staging_directory() -> Result<PathBuf, Error> {
+    return Ok(user_home()?.join("staging"));
+}
+
+
+/**
+Sets the home directory used by the rest of the process. It can only be set
+once, and must be set before anything is read or written.
+*/
+pub fn set_home_directory(path: PathBuf) -> Result<(), Error> {
+    let path = make_absolute(&path)?;
+    let current = HOME_DIRECTORY.get_or_init(|| path.clone());
+    if *current != path {
+        return Err(Error::Config(format!("The home directory is already set to {}", current.to_string_lossy())));
+    }
+    return Ok(());
+}
+
 
-pub fn get_home() -> Result<Option<PathBuf>, GetHomeError> {
-    get_my_home().unwrap().unwrap().as_path()?
+/// Gets the home directory, resolving it from the environment if it has not
+/// been set
+pub fn home_directory() -> Result<PathBuf, Error> {
+    if let Some(home) = HOME_DIRECTORY.get() {
+        return Ok(home.clone());
+    }
+    let home = resolve_home_directory(None)?;
+    return Ok(HOME_DIRECTORY.get_or_init(|| home).clone());
 }
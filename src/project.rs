@@ -1,56 +1,84 @@
-use edit;
 use std::collections::HashMap;
 
+use chrono::{NaiveDate, TimeDelta};
 use serde::{Deserialize, Serialize};
-use std::path::{PathBuf};
-use homedir::get_my_home;
-use log::{trace, warn, error};
+use log::{trace, info, warn};
 
 use crate::cli;
-use crate::file_utils::{make_directory, read_file_to_string, write_string_to_file};
-use crate::datetime::{validate_datetime_format};
+use crate::datetime::{format_date, home_timezone, parse_absolute_date, today_in};
+use crate::error::Error;
+use crate::event::{Event, load_staged_events};
+use crate::ledger::load_committed_events;
+use crate::lock::lock_data_directory;
+use crate::safeties::enforce;
+use crate::storage::open_storage;
 
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Project {
 
-    name: String,
+    pub name: String,
 
     /// Total amount of time allocated to the project, in hours
-    total_time: f32,
+    pub total_time: f32,
 
     /// Indicates whether or not the project is active or not
     /// Setting this flag to false will prevent you from accidentally charging
     /// it.
-    active: bool,
+    pub active: bool,
 
     /// Whether or not the project is "real"
     /// A real project has features on it enabled such as being able to add
     /// time to it. Not being real means it's basically a placeholder.
-    real: bool,
+    pub real: bool,
 
-    start_date: String,
-    end_date: String,
+    pub start_date: String,
+    pub end_date: String,
 
     /// Other metadata
-    metadata: HashMap<String, String>
+    pub metadata: HashMap<String, String>
+}
+
+
+/// Parses one of the dates of a project, either 21-Nov-23 or 2023-11-21
+fn parse_project_date(field: &str, value: &str) -> Result<NaiveDate, Error> {
+    match parse_absolute_date(value) {
+        Some(date) => { return Ok(date); },
+        None => { return Err(Error::Project(format!("{} {} invalid format, expected e.g. 21-Nov-23 or 2023-11-21!", field, value))); }
+    }
 }
 
 
 impl Project {
 
     /**
-    Validates the integrity of the project. Will also throw warnings where
-    necessary (for example, when active is false).
+    Validates the integrity of the project.
     */
-    fn validate(&self) {
-        if !validate_datetime_format(&self.start_date) {
-            error!("start_date {} invalid format!", self.start_date);
-            panic!();
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_project_name(&self.name)?;
+        if self.start()? > self.end()? {
+            return Err(Error::Project(format!("start_date {} is after end_date {}!", self.start_date, self.end_date)));
         }
-        if !validate_datetime_format(&self.end_date) {
-            error!("end_date {} invalid format!", self.end_date);
-            panic!();
+        return Ok(());
+    }
+
+    /// Parses the start date of the project
+    pub fn start(&self) -> Result<NaiveDate, Error> {
+        return parse_project_date("start_date", &self.start_date);
+    }
+
+    /// Parses the end date of the project
+    pub fn end(&self) -> Result<NaiveDate, Error> {
+        return parse_project_date("end_date", &self.end_date);
+    }
+
+    /// Throws warnings where necessary (for example, when active is false)
+    fn log_warnings(&self) {
+        if !self.active {
+            warn!("Project {} is not active, time cannot be charged to it", self.name);
+        }
+        if !self.real {
+            warn!("Project {} is not real, it is only a placeholder", self.name);
         }
     }
 
@@ -70,20 +98,303 @@ pub fn get_default_project() -> Project {
         end_date: "01-Oct-24".to_string(),
         metadata: example_metadata
     };
-    default_project.validate();
     trace!("Default project retrieved: {:?}", default_project);
     return default_project;
 }
 
 
-pub fn execute(args: &cli::cli_project::ProjectCommand) {
+/// Project names double as file names, so they must be plain and non-empty
+pub fn validate_project_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::Project("Project name cannot be empty".to_string()));
+    }
+    if name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(Error::Project(format!("Project name {:?} cannot start with '.' or contain path separators", name)));
+    }
+    return Ok(());
+}
+
+
+/// Parses and validates a project yaml string, checking that it is stored
+/// under its own name
+fn parse_project(content: &str, name: &str) -> Result<Project, Error> {
+    let project: Project = serde_yaml::from_str(content)?;
+    if project.name != name {
+        return Err(Error::Project(format!("Project name {:?} does not match its file name {:?}", project.name, name)));
+    }
+    project.validate()?;
+    project.log_warnings();
+    return Ok(project);
+}
+
+
+/// Checks a project read from storage, which may have been edited by hand
+fn check_stored_project(project: Project) -> Result<Project, Error> {
+    match project.validate() {
+        Ok(()) => { return Ok(project); },
+        Err(e) => {
+            return Err(Error::Project(format!(
+                "Stored project {} is invalid ({}), fix it with `dt project edit {}`", project.name, e, project.name
+            )));
+        }
+    }
+}
+
+
+/// Loads a single project from storage as it is, even if it is invalid, so
+/// that it can still be edited or removed
+fn load_stored_project(name: &str) -> Result<Option<Project>, Error> {
+    validate_project_name(name)?;
+    return open_storage()?.load_project(name);
+}
+
+
+/// Loads a single project from storage, if it exists
+pub fn load_project(name: &str) -> Result<Option<Project>, Error> {
+    return load_stored_project(name)?.map(check_stored_project).transpose();
+}
+
+
+/// Loads every valid project from storage, sorted by name. Invalid projects
+/// are left out with a warning, so that one bad file does not stop time
+/// from being charged to the others.
+pub fn load_projects() -> Result<Vec<Project>, Error> {
+    let mut projects: Vec<Project> = Vec::new();
+    for project in open_storage()?.load_projects()? {
+        match check_stored_project(project) {
+            Ok(project) => { projects.push(project); },
+            Err(e) => { warn!("{}. It is ignored until then", e); }
+        }
+    }
+    return Ok(projects);
+}
+
+
+/**
+Opens the project as yaml in the user's default editor and returns the edited
+project. Nothing is saved here, so an invalid edit leaves the stored project
+untouched.
+*/
+fn edit_project_yaml(name: &str, project: &Project) -> Result<Project, Error> {
+    let editor = edit::get_editor();
+    trace!("Editing project with default editor: {:?}", editor);
+
+    let content = serde_yaml::to_string(project)?;
+    let content_after_edit = edit::edit_with_builder(&content, edit::Builder::new().suffix(".yaml"))?;
+    match parse_project(&content_after_edit, name) {
+        Ok(project) => { return Ok(project); },
+        Err(e) => { return Err(Error::Project(format!("Project edit failed, the project was left unchanged: {}", e))); }
+    }
+}
+
+
+/// Stores a new project, refusing to overwrite an existing one
+pub fn create_project(project: &Project) -> Result<(), Error> {
+    if load_stored_project(&project.name)?.is_some() {
+        return Err(Error::Project(format!("Project {} already exists, use `dt project edit {}`", project.name, project.name)));
+    }
+    project.validate()?;
+    open_storage()?.save_project(project)?;
+    return Ok(());
+}
+
+
+/// Overwrites an existing project with new settings
+pub fn update_project(project: &Project) -> Result<(), Error> {
+    if load_stored_project(&project.name)?.is_none() {
+        return Err(Error::Project(format!("Project {} does not exist, use `dt project add {}`", project.name, project.name)));
+    }
+    project.validate()?;
+    open_storage()?.save_project(project)?;
+    return Ok(());
+}
+
+
+/**
+Removes a project. Removing a project that has committed time would orphan
+those events, which is refused unless the safeties are off.
+*/
+pub fn delete_project(name: &str, safeties_on: bool) -> Result<(), Error> {
+    if load_stored_project(name)?.is_none() {
+        return Err(Error::Project(format!("Project {} does not exist", name)));
+    }
+
+    let n_committed = load_committed_events()?.iter().filter(|e| e.project == name).count();
+    let check = match n_committed {
+        0 => Ok(()),
+        n => Err(format!("Project {} has {} committed event(s)", name, n))
+    };
+    enforce(check, safeties_on)?;
+
+    open_storage()?.remove_project(name)?;
+    return Ok(());
+}
+
+
+fn add_project(args: &cli::cli_project::ProjectAddCommand) -> Result<(), Error> {
+    if load_stored_project(&args.name)?.is_some() {
+        return Err(Error::Project(format!("Project {} already exists, use `dt project edit {}`", args.name, args.name)));
+    }
+
+    let mut project = get_default_project();
+    project.name = args.name.clone();
+    if !args.no_edit {
+        // A project that failed to edit on creation is never stored
+        project = edit_project_yaml(&args.name, &project)?;
+    }
+
+    // The data is only locked once the editor is closed, creating the project
+    // checks again that it does not exist
+    let _lock = lock_data_directory()?;
+    create_project(&project)?;
+    info!("Project {} added", args.name);
+    return Ok(());
+}
+
+
+fn edit_project(args: &cli::cli_project::ProjectEditCommand) -> Result<(), Error> {
+    let project = match load_stored_project(&args.name)? {
+        Some(project) => project,
+        None => {
+            return Err(Error::Project(format!("Project {} does not exist, use `dt project add {}`", args.name, args.name)));
+        }
+    };
+    let edited = edit_project_yaml(&args.name, &project)?;
+
+    // The data is only locked once the editor is closed, so the project may
+    // have been changed by another command in the meantime
+    let _lock = lock_data_directory()?;
+    let current = load_stored_project(&args.name)?;
+    if serde_json::to_value(&current)? != serde_json::to_value(Some(&project))? {
+        return Err(Error::Project(format!(
+            "Project {} was changed by another command while it was being edited, it was left unchanged", args.name
+        )));
+    }
+    update_project(&edited)?;
+    info!("Project {} edited", args.name);
+    return Ok(());
+}
+
+
+fn show_project(args: &cli::cli_project::ProjectShowCommand) -> Result<(), Error> {
+    match load_project(&args.name)? {
+        Some(project) => {
+            println!("{:#?}", project);
+            project.log_warnings();
+            return Ok(());
+        },
+        None => {
+            return Err(Error::Project(format!("Project {} does not exist", args.name)));
+        }
+    }
+}
+
+
+fn list_projects() -> Result<(), Error> {
+    let projects = load_projects()?;
+    if projects.is_empty() {
+        info!("No projects yet, add one with `dt project add <name>`");
+        return Ok(());
+    }
+    println!("{:<20} {:<6} {:<5} {:>8}  {:<10} END", "NAME", "ACTIVE", "REAL", "HOURS", "START");
+    for project in projects.iter() {
+        println!(
+            "{:<20} {:<6} {:<5} {:>8.2}  {:<10} {}",
+            project.name, project.active, project.real, project.total_time, project.start_date, project.end_date
+        );
+    }
+    return Ok(());
+}
+
+
+fn remove_project(args: &cli::cli_project::ProjectRemoveCommand, safeties_on: bool) -> Result<(), Error> {
+    delete_project(&args.name, safeties_on)?;
+    info!("Project {} removed", args.name);
+    return Ok(());
+}
+
+
+/// Sums the hours logged against a project
+pub fn hours_used(name: &str, events: &[Event]) -> f32 {
+    return events.iter().filter(|e| e.project == name).fold(0.0, |total, e| total + e.hours);
+}
+
+
+/**
+Projects the date at which the remaining hours run out, assuming time keeps
+being burned at the average rate since the project started.
+*/
+fn projected_exhaustion(project: &Project, used: f32, today: NaiveDate) -> Result<String, Error> {
+    let remaining = project.total_time - used;
+    if remaining <= 0.0 {
+        return Ok("exhausted".to_string());
+    }
+    let elapsed_days = (today - project.start()?).num_days();
+    if elapsed_days <= 0 || used <= 0.0 {
+        return Ok("-".to_string());
+    }
+    let rate = used / elapsed_days as f32;
+    // At a very slow rate the hours never run out within the range of dates
+    let days_to_exhaustion = (remaining / rate).ceil() as i64;
+    match TimeDelta::try_days(days_to_exhaustion).and_then(|days| today.checked_add_signed(days)) {
+        Some(date) => { return Ok(format_date(date)); },
+        None => { return Ok("never".to_string()); }
+    }
+}
+
+
+fn project_status(args: &cli::cli_project::ProjectStatusCommand) -> Result<(), Error> {
+    let projects: Vec<Project> = match &args.name {
+        Some(name) => match load_project(name)? {
+            Some(project) => vec![project],
+            None => { return Err(Error::Project(format!("Project {} does not exist", name))); }
+        },
+        None => load_projects()?
+    };
+    if projects.is_empty() {
+        info!("No projects yet, add one with `dt project add <name>`");
+        return Ok(());
+    }
+
+    let mut events = load_committed_events()?;
+    if args.staged {
+        events.extend(load_staged_events()?);
+    }
+    let today = today_in(home_timezone());
+
+    println!(
+        "{:<20} {:>9} {:>9} {:>9} {:>7}  {:<12} DAYS LEFT",
+        "PROJECT", "ALLOCATED", "USED", "REMAINING", "BURNED", "EXHAUSTED BY"
+    );
+    for project in projects.iter() {
+        let used = hours_used(&project.name, &events);
+        let burned = if project.total_time > 0.0 {
+            format!("{:.1}%", 100.0 * used / project.total_time)
+        } else {
+            "-".to_string()
+        };
+        println!(
+            "{:<20} {:>9.2} {:>9.2} {:>9.2} {:>7}  {:<12} {}",
+            project.name, project.total_time, used, project.total_time - used, burned,
+            projected_exhaustion(project, used, today)?, (project.end()? - today).num_days()
+        );
+    }
+    return Ok(());
+}
+
+
+pub fn execute(args: &cli::cli_project::ProjectCommand, safeties_on: bool) -> Result<(), Error> {
     let current_command = &args.command;
     match current_command {
-        Some(cli::cli_project::ProjectSubcommand::Edit(_)) => {},
-        Some(cli::cli_project::ProjectSubcommand::Show(_)) => {},
+        Some(cli::cli_project::ProjectSubcommand::Add(project_sc)) => {add_project(project_sc)?;},
+        Some(cli::cli_project::ProjectSubcommand::Edit(project_sc)) => {edit_project(project_sc)?;},
+        Some(cli::cli_project::ProjectSubcommand::Show(project_sc)) => {show_project(project_sc)?;},
+        Some(cli::cli_project::ProjectSubcommand::List(_)) => {list_projects()?;},
+        Some(cli::cli_project::ProjectSubcommand::Remove(project_sc)) => {remove_project(project_sc, safeties_on)?;},
+        Some(cli::cli_project::ProjectSubcommand::Status(project_sc)) => {project_status(project_sc)?;},
         None => {
-            let _ = get_default_project();
             warn!("No project option provided!");
         }
     }
+    return Ok(());
 }
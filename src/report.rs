@@ -0,0 +1,301 @@
+/*!
+Timesheet reports, summarizing hours as a grid of projects by days.
+*/
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate, TimeDelta, Weekday};
+use chrono_tz::Tz;
+use log::{trace, info, warn};
+
+use crate::cli;
+use crate::config::{Config, load_config};
+use crate::datetime::{format_date, home_timezone, parse_command_line_date_in, parse_timezone};
+use crate::error::Error;
+use crate::event::{Event, load_staged_events};
+use crate::ledger::load_committed_events;
+use crate::project::load_projects;
+
+
+/**
+Parses a date provided to the report. On top of the usual command line date
+formats, month-only dates such as Nov-23 are accepted.
+*/
+fn parse_report_date(value: &str, tz: Tz) -> Result<NaiveDate, Error> {
+    if let Ok(date) = NaiveDate::parse_from_str(&format!("01-{}", value), "%d-%b-%y") {
+        return Ok(date);
+    }
+    return parse_command_line_date_in(&Some(value.to_string()), tz);
+}
+
+
+/// Gets the timezone the report counts days in
+fn report_timezone(tz: &Option<String>) -> Result<Tz, Error> {
+    match tz {
+        Some(name) => { return parse_timezone(name); },
+        None => { return Ok(home_timezone()); }
+    }
+}
+
+
+/// Builds the error returned when the period around a date goes past the
+/// last (or first) date that can be represented
+fn out_of_range(date: NaiveDate) -> Error {
+    return Error::Parse(format!("Date {} is out of range", date));
+}
+
+
+/// Gets the first and last day of the week containing the date
+pub fn week_containing(date: NaiveDate, config: &Config) -> Result<(NaiveDate, NaiveDate), Error> {
+    let week_start = match Weekday::from_str(&config.week_start) {
+        Ok(weekday) => weekday,
+        Err(_) => {
+            return Err(Error::Config(format!("week_start {:?} in the config is not a valid day of the week", config.week_start)));
+        }
+    };
+    let offset = date.weekday().days_since(week_start);
+    let start = date.checked_sub_signed(TimeDelta::days(offset as i64)).ok_or_else(|| out_of_range(date))?;
+    let end = start.checked_add_signed(TimeDelta::days(6)).ok_or_else(|| out_of_range(date))?;
+    return Ok((start, end));
+}
+
+
+/// Gets the first and last day of the month containing the date
+pub fn month_containing(date: NaiveDate) -> Result<(NaiveDate, NaiveDate), Error> {
+    let start = date.with_day(1).ok_or_else(|| out_of_range(date))?;
+    let next_month = match date.month() {
+        12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1)
+    };
+    let end = next_month.and_then(|d| d.pred_opt()).ok_or_else(|| out_of_range(date))?;
+    return Ok((start, end));
+}
+
+
+/// Works out the inclusive date range the report covers
+fn report_range(args: &cli::cli_report::ReportCommand, config: &Config, tz: Tz) -> Result<(NaiveDate, NaiveDate), Error> {
+    if let Some(month) = &args.month {
+        return month_containing(parse_report_date(month, tz)?);
+    }
+    if let Some(from) = &args.from {
+        let from = parse_report_date(from, tz)?;
+        let to = parse_report_date(args.to.as_deref().unwrap_or("today"), tz)?;
+        if from > to {
+            return Err(Error::Validation(format!("--from {} is after --to {}", format_date(from), format_date(to))));
+        }
+        return Ok((from, to));
+    }
+    let date = parse_report_date(args.week.as_deref().unwrap_or("today"), tz)?;
+    return week_containing(date, config);
+}
+
+
+/// Loads the committed events, and optionally the staged ones, that fall
+/// within the inclusive date range when days are counted in the timezone
+pub fn load_events_in_range(start: NaiveDate, end: NaiveDate, staged: bool, tz: Tz) -> Result<Vec<Event>, Error> {
+    let mut events = load_committed_events()?;
+    if staged {
+        events.extend(load_staged_events()?);
+    }
+    let mut in_range: Vec<Event> = Vec::new();
+    for event in events.into_iter() {
+        let date = event.date_in(tz)?;
+        if date >= start && date <= end {
+            in_range.push(event);
+        }
+    }
+    trace!("{} events between {} and {}", in_range.len(), start, end);
+    return Ok(in_range);
+}
+
+
+/// Prints the grid of hours per project (rows) and day (columns)
+fn print_grid(events: &[Event], start: NaiveDate, end: NaiveDate, config: &Config, tz: Tz) -> Result<(), Error> {
+    let days: Vec<NaiveDate> = start.iter_days().take_while(|d| *d <= end).collect();
+
+    // Accumulate hours per project per day
+    let mut grid: BTreeMap<&str, BTreeMap<NaiveDate, f32>> = BTreeMap::new();
+    for event in events.iter() {
+        let row = grid.entry(event.project.as_str()).or_default();
+        *row.entry(event.date_in(tz)?).or_insert(0.0) += event.hours;
+    }
+
+    let mut weekdays = format!("{:<20}", "");
+    let mut header = format!("{:<20}", "PROJECT");
+    for day in days.iter() {
+        weekdays.push_str(&format!(" {:>6}", day.format("%a").to_string()));
+        header.push_str(&format!(" {:>6}", day.format("%d").to_string()));
+    }
+    println!("{}", weekdays);
+    println!("{} {:>8}", header, "TOTAL");
+
+    for (project, row) in grid.iter() {
+        let mut line = format!("{:<20}", project);
+        for day in days.iter() {
+            match row.get(day) {
+                Some(hours) => line.push_str(&format!(" {:>6.2}", hours)),
+                None => line.push_str(&format!(" {:>6}", "."))
+            }
+        }
+        let total: f32 = row.values().fold(0.0, |total, h| total + h);
+        println!("{} {:>8.2}", line, total);
+    }
+
+    // Column totals, flagging any day over the configured maximum
+    let mut line = format!("{:<20}", "TOTAL");
+    let mut flagged: Vec<NaiveDate> = Vec::new();
+    for day in days.iter() {
+        let total: f32 = grid.values().filter_map(|row| row.get(day)).fold(0.0, |total, h| total + h);
+        if total > config.max_hours_per_day {
+            flagged.push(*day);
+            line.push_str(&format!(" {:>5.2}!", total));
+        } else {
+            line.push_str(&format!(" {:>6.2}", total));
+        }
+    }
+    let grand_total: f32 = events.iter().fold(0.0, |total, e| total + e.hours);
+    println!("{} {:>8.2}", line, grand_total);
+
+    for day in flagged.iter() {
+        println!("! {} exceeds max_hours_per_day of {}", format_date(*day), config.max_hours_per_day);
+    }
+    return Ok(());
+}
+
+
+/**
+Rounds the hours into integer percentages of their total using the largest
+remainder method, so that the percentages always sum to exactly 100. Ties go
+to the first of the hours. Without any hours every percentage is 0.
+*/
+fn percentages_summing_to_100(hours: &[f32]) -> Vec<u32> {
+    let total: f32 = hours.iter().fold(0.0, |total, h| total + h);
+    if total <= 0.0 {
+        return vec![0; hours.len()];
+    }
+    let exact: Vec<f32> = hours.iter().map(|h| 100.0 * h / total).collect();
+    let mut rounded: Vec<u32> = exact.iter().map(|p| p.floor() as u32).collect();
+
+    // Hand out the missing points to the largest fractional remainders
+    let missing = 100 - rounded.iter().sum::<u32>();
+    let mut order: Vec<usize> = (0..hours.len()).collect();
+    order.sort_by(|a, b| (exact[*b] - exact[*b].floor()).total_cmp(&(exact[*a] - exact[*a].floor())));
+    for ii in order.into_iter().take(missing as usize) {
+        rounded[ii] += 1;
+    }
+    return rounded;
+}
+
+
+fn effort(args: &cli::cli_report::ReportEffortCommand, config: &Config) -> Result<(), Error> {
+    let tz = report_timezone(&args.tz)?;
+    let (start, end) = month_containing(parse_report_date(args.month.as_deref().unwrap_or("today"), tz)?)?;
+    let events = load_events_in_range(start, end, args.staged, tz)?;
+    let projects = load_projects()?;
+
+    // Sum the hours per group, which is either the project itself or the
+    // value of the chosen metadata key on the project
+    let mut groups: BTreeMap<String, f32> = BTreeMap::new();
+    for event in events.iter() {
+        let group = match &args.group_by {
+            None => event.project.clone(),
+            Some(key) => {
+                let value = projects.iter()
+                    .find(|p| p.name == event.project)
+                    .and_then(|p| p.metadata.get(key));
+                match value {
+                    Some(value) => value.clone(),
+                    None => {
+                        warn!("Project {} has no metadata {:?}, grouping it on its own", event.project, key);
+                        format!("{} (no {})", event.project, key)
+                    }
+                }
+            }
+        };
+        *groups.entry(group).or_insert(0.0) += event.hours;
+    }
+    groups.retain(|_, hours| *hours > 0.0);
+
+    let period = format!("{} to {}", format_date(start), format_date(end));
+    if groups.is_empty() {
+        info!("No effort to report between {}", period);
+        return Ok(());
+    }
+
+    let hours: Vec<f32> = groups.values().cloned().collect();
+    let percentages = percentages_summing_to_100(&hours);
+    let total: f32 = hours.iter().fold(0.0, |total, h| total + h);
+    let label = args.group_by.clone().unwrap_or("PROJECT".to_string());
+
+    println!("EFFORT CERTIFICATION");
+    println!("Period: {}", period);
+    println!();
+    println!("{:<30} {:>8} {:>7}", label, "HOURS", "EFFORT");
+    for ((group, hours), percentage) in groups.iter().zip(percentages.iter()) {
+        println!("{:<30} {:>8.2} {:>6}%", group, hours, percentage);
+    }
+    println!("{:<30} {:>8.2} {:>6}%", "TOTAL", total, 100);
+    println!();
+    println!("I certify that the distribution of effort shown above is a reasonable");
+    println!("estimate of the work performed during the period {}.", period);
+    println!();
+    match config.metadata.get("my name is") {
+        Some(name) => println!("Name:      {}", name),
+        None => println!("Name:      ______________________________")
+    }
+    println!("Signature: ______________________________");
+    println!("Date:      ______________________________");
+    return Ok(());
+}
+
+
+pub fn execute(args: &cli::cli_report::ReportCommand) -> Result<(), Error> {
+    let config = load_config()?;
+    if let Some(cli::cli_report::ReportSubcommand::Effort(effort_sc)) = &args.command {
+        return effort(effort_sc, &config);
+    }
+
+    let tz = report_timezone(&args.tz)?;
+    let (start, end) = report_range(args, &config, tz)?;
+    let events = load_events_in_range(start, end, args.staged, tz)?;
+
+    println!("Timesheet {} to {} ({})", format_date(start), format_date(end), tz);
+    println!();
+    if events.is_empty() {
+        info!("No events between {} and {}", format_date(start), format_date(end));
+        return Ok(());
+    }
+    print_grid(&events, start, end, &config, tz)?;
+    return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentages_sum_to_100_when_rounding_down() {
+        assert_eq!(percentages_summing_to_100(&[1.0, 1.0, 1.0]), vec![34, 33, 33]);
+        assert_eq!(percentages_summing_to_100(&[2.0, 1.0, 1.0, 1.0, 1.0, 1.0]), vec![29, 15, 14, 14, 14, 14]);
+    }
+
+    #[test]
+    fn percentages_go_to_the_largest_remainders() {
+        // 16.67%, 33.33% and 50%: only the first remainder is large enough
+        assert_eq!(percentages_summing_to_100(&[1.0, 2.0, 3.0]), vec![17, 33, 50]);
+    }
+
+    #[test]
+    fn exact_percentages_are_kept() {
+        assert_eq!(percentages_summing_to_100(&[50.0, 25.0, 25.0]), vec![50, 25, 25]);
+        assert_eq!(percentages_summing_to_100(&[8.0]), vec![100]);
+    }
+
+    #[test]
+    fn no_hours_give_no_percentages() {
+        assert_eq!(percentages_summing_to_100(&[0.0, 0.0]), vec![0, 0]);
+        assert_eq!(percentages_summing_to_100(&[]), Vec::<u32>::new());
+    }
+}
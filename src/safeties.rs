@@ -0,0 +1,146 @@
+/*!
+Safety checks that guard against accidentally logging bad data. Every check
+returns a human readable reason on failure, and `enforce` decides whether that
+failure is fatal (safeties on) or merely a warning (`--disable-safeties`).
+*/
+
+use chrono::NaiveDate;
+use log::warn;
+
+use crate::config::Config;
+use crate::datetime::format_date;
+use crate::error::Error;
+use crate::event::Event;
+use crate::project::Project;
+
+
+/**
+Checks that adding `new_hours` to a day which already has `existing_hours`
+logged does not exceed the configured maximum number of hours per day.
+*/
+pub fn check_max_hours_per_day(date: NaiveDate, existing_hours: f32, new_hours: f32, config: &Config) -> Result<(), String> {
+    let total = existing_hours + new_hours;
+    if total > config.max_hours_per_day {
+        return Err(format!(
+            "Logging {:.2} hours on {} would bring the day to {:.2} hours, exceeding max_hours_per_day of {}",
+            new_hours, format_date(date), total, config.max_hours_per_day
+        ));
+    }
+    return Ok(());
+}
+
+
+/// Finds the name of the project closest to the provided (probably mistyped)
+/// name, if any is close enough to be a plausible typo
+fn suggest_project_name<'a>(name: &str, projects: &'a [Project]) -> Option<&'a str> {
+    let max_distance = std::cmp::max(2, name.len() / 3);
+    return projects.iter()
+        .map(|p| (strsim::levenshtein(&name.to_lowercase(), &p.name.to_lowercase()), p.name.as_str()))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name);
+}
+
+
+/**
+Checks that the project exists, is active and is real, since only such
+projects can have time charged to them.
+*/
+pub fn check_project(name: &str, projects: &[Project]) -> Result<(), String> {
+    let project = match projects.iter().find(|p| p.name == name) {
+        Some(project) => project,
+        None => {
+            let hint = match suggest_project_name(name, projects) {
+                Some(suggestion) => format!("Did you mean {:?}?", suggestion),
+                None => "Add it with `dt project add`".to_string()
+            };
+            return Err(format!("Project {:?} does not exist. {}", name, hint));
+        }
+    };
+    if !project.active {
+        return Err(format!("Project {:?} is not active", name));
+    }
+    if !project.real {
+        return Err(format!("Project {:?} is not real, it is only a placeholder", name));
+    }
+    return Ok(());
+}
+
+
+/**
+Checks that the date falls within the start and end dates of the project, so
+that expired (or not yet started) projects are not charged.
+*/
+pub fn check_project_window(project: &Project, date: NaiveDate) -> Result<(), String> {
+    let (start, end) = match (project.start(), project.end()) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => { return Err(format!("Project {:?} has no valid dates: {}", project.name, e)); }
+    };
+    if date < start || date > end {
+        return Err(format!(
+            "Date {} is outside of project {:?} which runs from {} to {}",
+            format_date(date), project.name, project.start_date, project.end_date
+        ));
+    }
+    return Ok(());
+}
+
+
+/**
+Checks that adding `new_hours` to a project which already has `used_hours`
+logged stays within the total time allocated to it.
+*/
+pub fn check_project_budget(project: &Project, used_hours: f32, new_hours: f32) -> Result<(), String> {
+    let total = used_hours + new_hours;
+    if total > project.total_time {
+        return Err(format!(
+            "Logging {:.2} hours on project {:?} brings it to {:.2} hours, exceeding its allocation of {}",
+            new_hours, project.name, total, project.total_time
+        ));
+    }
+    return Ok(());
+}
+
+
+/**
+Checks that the time span of the event does not overlap with that of any of
+the other events. Events without clock times cannot overlap. Spans are
+compared as instants, so events logged in different timezones are compared
+correctly, even when they fall on different local dates.
+*/
+pub fn check_overlap(event: &Event, others: &[&Event]) -> Result<(), String> {
+    let (start, end) = match event.instants() {
+        Some(instants) => instants,
+        None => { return Ok(()); }
+    };
+    for other in others.iter().filter(|o| o.uuid != event.uuid) {
+        if let Some((other_start, other_end)) = other.instants() {
+            if start < other_end && other_start < end {
+                return Err(format!(
+                    "Event {}-{} on {} overlaps with event {} ({:?}, {}-{})",
+                    event.start.as_deref().unwrap_or(""), event.end.as_deref().unwrap_or(""), event.display_date(),
+                    &other.uuid[..8], other.project, other.start.as_deref().unwrap_or(""), other.end.as_deref().unwrap_or("")
+                ));
+            }
+        }
+    }
+    return Ok(());
+}
+
+
+/**
+Enforces the result of a safety check. If safeties are on, a failed check is
+turned into an error; otherwise it is logged as a warning and ignored.
+*/
+pub fn enforce(check: Result<(), String>, safeties_on: bool) -> Result<(), Error> {
+    match check {
+        Ok(()) => { return Ok(()); },
+        Err(reason) => {
+            if safeties_on {
+                return Err(Error::Validation(format!("{} (use --disable-safeties to override)", reason)));
+            }
+            warn!("{} (ignored since safeties are disabled)", reason);
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,385 @@
+/*!
+Versioning of everything Doubletime persists. Every staged event, commit,
+project, config and timer file carries a `schema_version`, and files written
+before versions existed are version 0.
+
+Older records are upgraded in memory when they are read, by running the
+migrations from their version up to the current one, so existing data keeps
+working as the structs change. `dt migrate` rewrites them in the current
+version for good. Records written by a newer version of Doubletime are refused
+rather than misread.
+
+A change to a persisted struct that older files cannot be read into bumps
+`SCHEMA_VERSION` and adds one migration per kind of record to `MIGRATIONS`.
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::NaiveDate;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use log::{debug, warn};
+
+use crate::datetime::format_stored_date;
+use crate::error::Error;
+
+
+/// Version every record is written in
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Name of the version field in every persisted record
+const VERSION_FIELD: &str = "schema_version";
+
+/// Whether the user was already told that some data is out of date
+static UPGRADE_NOTICE: AtomicBool = AtomicBool::new(false);
+
+
+/// Kinds of records that are persisted, each with its own migrations
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Record {
+    Event,
+    Commit,
+    Project,
+    Config,
+    Timers
+}
+
+
+/// Upgrade of one kind of record from a version to the next one
+struct Migration {
+    record: Record,
+    from: u32,
+    apply: fn(&mut Value) -> Result<(), Error>
+}
+
+
+/// Every migration, in order
+const MIGRATIONS: &[Migration] = &[
+    Migration { record: Record::Event, from: 0, apply: event_v0_to_v1 },
+    Migration { record: Record::Commit, from: 0, apply: commit_v0_to_v1 },
+    Migration { record: Record::Project, from: 0, apply: unchanged },
+    Migration { record: Record::Config, from: 0, apply: unchanged },
+    Migration { record: Record::Timers, from: 0, apply: timers_v0_to_v1 },
+    Migration { record: Record::Event, from: 1, apply: event_v1_to_v2 },
+    Migration { record: Record::Commit, from: 1, apply: commit_v1_to_v2 },
+    Migration { record: Record::Project, from: 1, apply: unchanged },
+    Migration { record: Record::Config, from: 1, apply: unchanged },
+    Migration { record: Record::Timers, from: 1, apply: timers_v1_to_v2 }
+];
+
+
+/// For records whose layout did not change, only their version does
+fn unchanged(_value: &mut Value) -> Result<(), Error> {
+    return Ok(());
+}
+
+
+/// The first staged events had no duration, they are given 0 hours so that
+/// they can be found and fixed
+fn event_v0_to_v1(value: &mut Value) -> Result<(), Error> {
+    let event = match value.as_object_mut() {
+        Some(event) => event,
+        None => { return Err(Error::Storage("Stored event is not an object".to_string())); }
+    };
+    if !event.contains_key("hours") {
+        let uuid = event.get("uuid").and_then(|uuid| uuid.as_str()).unwrap_or("?").to_string();
+        warn!("Event {} has no duration, it was given 0 hours", uuid);
+        event.insert("hours".to_string(), Value::from(0.0));
+    }
+    return Ok(());
+}
+
+
+/// Commits hold events, which are upgraded along with them
+fn commit_v0_to_v1(value: &mut Value) -> Result<(), Error> {
+    if let Some(events) = value.get_mut("events").and_then(|events| events.as_array_mut()) {
+        for event in events.iter_mut() {
+            event_v0_to_v1(event)?;
+        }
+    }
+    return Ok(());
+}
+
+
+/// The timer file was a bare list of timers, it is now an object holding
+/// the list next to its version
+fn timers_v0_to_v1(value: &mut Value) -> Result<(), Error> {
+    if value.is_array() {
+        let timers = value.take();
+        *value = serde_json::json!({ "timers": timers });
+    }
+    return Ok(());
+}
+
+
+/**
+Rewrites the `now_str` date of a record from `%d-%b-%y` to `%Y-%m-%d`. Two
+digit years were always read as 1969 to 2068, which is kept here.
+*/
+fn four_digit_year_v1_to_v2(value: &mut Value, record: Record) -> Result<(), Error> {
+    let now_str = match value.get_mut("now_str") {
+        Some(now_str) => now_str,
+        None => { return Err(Error::Storage(format!("Stored {:?} has no now_str", record))); }
+    };
+    let date = now_str.as_str()
+        .and_then(|date| NaiveDate::parse_from_str(date, "%d-%b-%y").ok())
+        .ok_or_else(|| Error::Storage(format!("Stored {:?} date {} is not valid", record, now_str)))?;
+    *now_str = Value::from(format_stored_date(date));
+    return Ok(());
+}
+
+
+/// Event dates are stored with a four digit year
+fn event_v1_to_v2(value: &mut Value) -> Result<(), Error> {
+    return four_digit_year_v1_to_v2(value, Record::Event);
+}
+
+
+/// Commits hold events, which are upgraded along with them
+fn commit_v1_to_v2(value: &mut Value) -> Result<(), Error> {
+    if let Some(events) = value.get_mut("events").and_then(|events| events.as_array_mut()) {
+        for event in events.iter_mut() {
+            event_v1_to_v2(event)?;
+        }
+    }
+    return Ok(());
+}
+
+
+/// Timer dates are stored with a four digit year, like event dates
+fn timers_v1_to_v2(value: &mut Value) -> Result<(), Error> {
+    if let Some(timers) = value.get_mut("timers").and_then(|timers| timers.as_array_mut()) {
+        for timer in timers.iter_mut() {
+            four_digit_year_v1_to_v2(timer, Record::Timers)?;
+        }
+    }
+    return Ok(());
+}
+
+
+/// A record along with the version it is written in, which comes first
+#[derive(Serialize)]
+struct Versioned<'a, T: Serialize> {
+    schema_version: u32,
+
+    #[serde(flatten)]
+    record: &'a T
+}
+
+
+impl<'a, T: Serialize> Versioned<'a, T> {
+    fn new(record: &'a T) -> Versioned<'a, T> {
+        return Versioned { schema_version: SCHEMA_VERSION, record };
+    }
+}
+
+
+/// Serializes a record as json in the current version
+pub fn to_json<T: Serialize>(record: &T) -> Result<String, Error> {
+    return Ok(serde_json::to_string(&Versioned::new(record))?);
+}
+
+
+/// Serializes a record as indented json in the current version
+pub fn to_json_pretty<T: Serialize>(record: &T) -> Result<String, Error> {
+    return Ok(serde_json::to_string_pretty(&Versioned::new(record))?);
+}
+
+
+/// Serializes a record as yaml in the current version
+pub fn to_yaml<T: Serialize>(record: &T) -> Result<String, Error> {
+    return Ok(serde_yaml::to_string(&Versioned::new(record))?);
+}
+
+
+/// Gets the version a parsed record was written in, removing it from the
+/// record. Records without a version predate versioning.
+fn take_version(value: &mut Value) -> Result<u32, Error> {
+    let version = match value.as_object_mut().and_then(|object| object.remove(VERSION_FIELD)) {
+        Some(version) => version,
+        None => { return Ok(0); }
+    };
+    match version.as_u64() {
+        Some(version) => { return Ok(version as u32); },
+        None => { return Err(Error::Storage(format!("{} {} is not a valid version", VERSION_FIELD, version))); }
+    }
+}
+
+
+/// Runs the migrations needed to bring a record from its version up to the
+/// current one
+fn upgrade(record: Record, value: &mut Value, version: u32) -> Result<(), Error> {
+    if version > SCHEMA_VERSION {
+        return Err(Error::Storage(format!(
+            "{:?} was written by a newer version of Doubletime (schema version {}, this version reads up to {}), upgrade dt",
+            record, version, SCHEMA_VERSION
+        )));
+    }
+    if version < SCHEMA_VERSION && !UPGRADE_NOTICE.swap(true, Ordering::Relaxed) {
+        warn!("Some data was written by an older version of Doubletime, run `dt migrate` to upgrade it");
+    }
+    for from in version..SCHEMA_VERSION {
+        let migration = MIGRATIONS.iter().find(|m| m.record == record && m.from == from);
+        match migration {
+            Some(migration) => {
+                debug!("Upgrading {:?} from schema version {} to {}", record, from, from + 1);
+                (migration.apply)(value)?;
+            },
+            None => { return Err(Error::Storage(format!("No migration of {:?} from schema version {}", record, from))); }
+        }
+    }
+    return Ok(());
+}
+
+
+/// Upgrades a parsed record to the current version and deserializes it
+fn read_value<T: DeserializeOwned>(mut value: Value, record: Record) -> Result<T, Error> {
+    let version = take_version(&mut value)?;
+    upgrade(record, &mut value, version)?;
+    return Ok(serde_json::from_value(value)?);
+}
+
+
+/// Reads a json record written in any version up to the current one
+pub fn from_json<T: DeserializeOwned>(content: &str, record: Record) -> Result<T, Error> {
+    return read_value(serde_json::from_str(content)?, record);
+}
+
+
+/// Reads a yaml record written in any version up to the current one
+pub fn from_yaml<T: DeserializeOwned>(content: &str, record: Record) -> Result<T, Error> {
+    return read_value(serde_yaml::from_str(content)?, record);
+}
+
+
+/// Gets the version a json record was written in
+pub fn json_version(content: &str) -> Result<u32, Error> {
+    return take_version(&mut serde_json::from_str(content)?);
+}
+
+
+/// Gets the version a yaml record was written in
+pub fn yaml_version(content: &str) -> Result<u32, Error> {
+    return take_version(&mut serde_yaml::from_str(content)?);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use crate::ledger::Commit;
+    use crate::project::Project;
+    use crate::timer::TimerFile;
+
+    const EVENT_V0: &str = r#"{"timestamp":1700000000,"message":"m","project":"p","now_str":"14-Nov-23","uuid":"abc"}"#;
+
+    #[test]
+    fn take_version_defaults_to_zero() {
+        let mut value: Value = serde_json::from_str(EVENT_V0).unwrap();
+        assert_eq!(take_version(&mut value).unwrap(), 0);
+
+        let mut value = serde_json::json!({ "schema_version": 1, "uuid": "abc" });
+        assert_eq!(take_version(&mut value).unwrap(), 1);
+        assert!(value.get(VERSION_FIELD).is_none());
+
+        let mut value = serde_json::json!({ "schema_version": "one" });
+        assert!(take_version(&mut value).is_err());
+    }
+
+    #[test]
+    fn v0_event_is_given_zero_hours() {
+        let event: Event = from_json(EVENT_V0, Record::Event).unwrap();
+        assert_eq!(event.uuid, "abc");
+        assert_eq!(event.hours, 0.0);
+    }
+
+    #[test]
+    fn v0_event_keeps_its_hours() {
+        let content = r#"{"timestamp":1700000000,"message":"m","project":"p","now_str":"14-Nov-23","uuid":"abc","hours":2.5}"#;
+        let event: Event = from_json(content, Record::Event).unwrap();
+        assert_eq!(event.hours, 2.5);
+    }
+
+    #[test]
+    fn v0_commit_upgrades_its_events() {
+        let content = format!(r#"{{"commit_id":"c1","parent":null,"message":"m","timestamp":1700000000,"events":[{}]}}"#, EVENT_V0);
+        let commit: Commit = from_json(&content, Record::Commit).unwrap();
+        assert_eq!(commit.events.len(), 1);
+        assert_eq!(commit.events[0].hours, 0.0);
+    }
+
+    #[test]
+    fn v0_timers_are_wrapped() {
+        let content = r#"[{"project":"p","message":"m","now_str":"14-Nov-23","started":1,"elapsed_seconds":60,"running_since":null}]"#;
+        let timers: TimerFile = from_json(content, Record::Timers).unwrap();
+        assert_eq!(timers.timers.len(), 1);
+        assert_eq!(timers.timers[0].elapsed_seconds, 60);
+    }
+
+    #[test]
+    fn v1_event_dates_get_a_four_digit_year() {
+        let content = r#"{"schema_version":1,"timestamp":1,"message":"m","project":"p","now_str":"05-Jan-70","uuid":"abc","hours":1.0}"#;
+        let event: Event = from_json(content, Record::Event).unwrap();
+        assert_eq!(event.now_str, "1970-01-05");
+
+        let content = r#"{"schema_version":1,"timestamp":1,"message":"m","project":"p","now_str":"31-Dec-68","uuid":"abc","hours":1.0}"#;
+        let event: Event = from_json(content, Record::Event).unwrap();
+        assert_eq!(event.now_str, "2068-12-31");
+
+        let content = r#"{"schema_version":1,"timestamp":1,"message":"m","project":"p","now_str":"2070-01-05","uuid":"abc","hours":1.0}"#;
+        assert!(matches!(from_json::<Event>(content, Record::Event), Err(Error::Storage(_))));
+    }
+
+    #[test]
+    fn v0_commit_and_timer_dates_get_a_four_digit_year() {
+        let content = format!(r#"{{"commit_id":"c1","parent":null,"message":"m","timestamp":1700000000,"events":[{}]}}"#, EVENT_V0);
+        let commit: Commit = from_json(&content, Record::Commit).unwrap();
+        assert_eq!(commit.events[0].now_str, "2023-11-14");
+
+        let content = r#"{"schema_version":1,"timers":[{"project":"p","message":"m","now_str":"14-Nov-23","started":1,"elapsed_seconds":60,"running_since":null}]}"#;
+        let timers: TimerFile = from_json(content, Record::Timers).unwrap();
+        assert_eq!(timers.timers[0].now_str, "2023-11-14");
+    }
+
+    #[test]
+    fn v0_yaml_project_is_read() {
+        let content = "name: p\ntotal_time: 10.0\nactive: true\nreal: true\nstart_date: 01-Jan-23\nend_date: 31-Dec-23\nmetadata: {}\n";
+        let project: Project = from_yaml(content, Record::Project).unwrap();
+        assert_eq!(project.name, "p");
+        assert_eq!(yaml_version(content).unwrap(), 0);
+    }
+
+    #[test]
+    fn records_round_trip_with_their_version_first() {
+        let event: Event = from_json(EVENT_V0, Record::Event).unwrap();
+        let content = to_json(&event).unwrap();
+        assert!(content.starts_with(&format!(r#"{{"schema_version":{},"#, SCHEMA_VERSION)));
+        assert_eq!(json_version(&content).unwrap(), SCHEMA_VERSION);
+        let read_back: Event = from_json(&content, Record::Event).unwrap();
+        assert_eq!(serde_json::to_value(&read_back).unwrap(), serde_json::to_value(&event).unwrap());
+
+        let content = to_yaml(&event).unwrap();
+        assert!(content.starts_with(&format!("schema_version: {}\n", SCHEMA_VERSION)));
+        let read_back: Event = from_yaml(&content, Record::Event).unwrap();
+        assert_eq!(read_back.uuid, event.uuid);
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let content = r#"{"schema_version":99,"timestamp":1,"message":"m","project":"p","now_str":"14-Nov-23","uuid":"abc","hours":1.0}"#;
+        let error = from_json::<Event>(content, Record::Event).unwrap_err();
+        assert!(matches!(error, Error::Storage(_)));
+        assert!(error.to_string().contains("newer version"));
+    }
+
+    #[test]
+    fn every_record_has_a_migration_for_every_version() {
+        for record in [Record::Event, Record::Commit, Record::Project, Record::Config, Record::Timers] {
+            for from in 0..SCHEMA_VERSION {
+                assert!(MIGRATIONS.iter().any(|m| m.record == record && m.from == from), "{:?} from {}", record, from);
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+/*!
+Persistence of everything Doubletime knows about: staged events, the ledger of
+commits, projects and the config. The `Storage` trait abstracts over where that
+data lives, with the original layout of json and yaml files in the Doubletime
+home directory as one backend and an embedded SQLite database as another.
+
+The backend in use is selected by the `storage` key of the config file, which
+is always read from disk so that the right backend can be opened.
+*/
+
+pub mod storage_file;
+pub mod storage_sqlite;
+
+use std::fs::{copy, rename};
+use std::path::PathBuf;
+
+use chrono::Utc;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use log::{trace, info};
+
+use crate::cli;
+use crate::config::{Config, get_backup_directory, get_config_path};
+use crate::error::Error;
+use crate::event::Event;
+use crate::file_utils::{make_directory, read_file_to_string, write_string_to_file_atomically};
+use crate::ledger::Commit;
+use crate::project::Project;
+use crate::schema::SCHEMA_VERSION;
+use crate::storage::storage_file::FileStorage;
+use crate::storage::storage_sqlite::SqliteStorage;
+
+
+/// Available storage backends
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// One json or yaml file per event, commit and project
+    #[default]
+    File,
+
+    /// A single embedded SQLite database
+    Sqlite
+}
+
+
+/// A backend persisting the staging area, the ledger, projects and the config
+pub trait Storage {
+
+    /// Loads every event currently sitting in the staging area
+    fn load_staged_events(&self) -> Result<Vec<Event>, Error>;
+
+    /// Adds an event to the staging area
+    fn stage_event(&self, event: &Event) -> Result<(), Error>;
+
+    /// Removes the event with the uuid from the staging area
+    fn unstage_event(&self, uuid: &str) -> Result<(), Error>;
+
+    /// Reads the id of the most recent commit, if anything has been committed
+    fn read_head(&self) -> Result<Option<String>, Error>;
+
+    /// Reads a single commit from the ledger
+    fn read_commit(&self, commit_id: &str) -> Result<Commit, Error>;
+
+    /// Writes a new commit to the ledger and moves HEAD to it. A failure part
+    /// way through must never leave HEAD pointing to a missing commit.
+    fn append_commit(&self, commit: &Commit) -> Result<(), Error>;
+
+    /// Loads every project, sorted by name
+    fn load_projects(&self) -> Result<Vec<Project>, Error>;
+
+    /// Loads a single project, if it exists
+    fn load_project(&self, name: &str) -> Result<Option<Project>, Error>;
+
+    /// Creates or overwrites a project
+    fn save_project(&self, project: &Project) -> Result<(), Error>;
+
+    /// Removes a project
+    fn remove_project(&self, name: &str) -> Result<(), Error>;
+
+    /// Loads the config
+    fn load_config(&self) -> Result<Config, Error>;
+
+    /// Creates or overwrites the config
+    fn save_config(&self, config: &Config) -> Result<(), Error>;
+
+    /// Rewrites every record written in an older schema version in the
+    /// current one, returning how many were (or with dry_run, would be)
+    /// rewritten
+    fn upgrade(&self, dry_run: bool) -> Result<usize, Error>;
+
+}
+
+
+/// The part of the config file that is needed before a backend is opened
+#[derive(Debug, Default, Deserialize)]
+struct StorageSettings {
+    #[serde(default)]
+    storage: StorageKind
+}
+
+
+/// Reads the kind of storage selected in the config file
+pub fn storage_kind() -> Result<StorageKind, Error> {
+    let config_path = get_config_path()?;
+    if !config_path.exists() {
+        return Ok(StorageKind::default());
+    }
+    let settings: StorageSettings = match serde_yaml::from_str(&read_file_to_string(config_path.clone())?) {
+        Ok(settings) => settings,
+        Err(e) => {
+            return Err(Error::Config(format!("Config file {} is invalid: {}", config_path.to_string_lossy(), e)));
+        }
+    };
+    return Ok(settings.storage);
+}
+
+
+/**
+Writes the config file of the SQLite storage, which only selects the backend
+since the config itself is stored in the database.
+*/
+pub fn write_sqlite_config_pointer() -> Result<(), Error> {
+    let pointer = format!(
+        "# The config is stored in the SQLite database, edit it with `dt config edit`\nschema_version: {}\nstorage: sqlite\n",
+        SCHEMA_VERSION
+    );
+    write_string_to_file_atomically(&pointer, get_config_path()?)?;
+    return Ok(());
+}
+
+
+/// Opens a specific storage backend
+fn open_kind(kind: StorageKind) -> Result<Box<dyn Storage>, Error> {
+    trace!("Opening {:?} storage", kind);
+    match kind {
+        StorageKind::File => { return Ok(Box::new(FileStorage::open()?)); },
+        StorageKind::Sqlite => { return Ok(Box::new(SqliteStorage::open()?)); }
+    }
+}
+
+
+/// Opens the storage backend selected in the config
+pub fn open_storage() -> Result<Box<dyn Storage>, Error> {
+    return open_kind(storage_kind()?);
+}
+
+
+/// Everything held by a storage backend, ordered so that two copies of the
+/// same data compare equal
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    staged: Vec<Event>,
+    history: Vec<Commit>,
+    projects: Vec<Project>,
+    config: Option<Config>
+}
+
+
+impl Snapshot {
+
+    /// Reads everything out of a storage backend
+    fn take(storage: &dyn Storage) -> Result<Snapshot, Error> {
+        let mut staged = storage.load_staged_events()?;
+        staged.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+
+        let mut history: Vec<Commit> = Vec::new();
+        let mut next = storage.read_head()?;
+        while let Some(commit_id) = next {
+            let commit = storage.read_commit(&commit_id)?;
+            next = commit.parent.clone();
+            history.push(commit);
+        }
+
+        let config = match storage.load_config() {
+            Ok(config) => Some(config),
+            Err(e) => {
+                trace!("No config to read: {}", e);
+                None
+            }
+        };
+        return Ok(Snapshot { staged, history, projects: storage.load_projects()?, config });
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.staged.is_empty() && self.history.is_empty() && self.projects.is_empty();
+    }
+
+    /// Json rendering of the data, so that copies can be compared exactly
+    /// regardless of the order of map keys
+    fn fingerprint(&self) -> Result<serde_json::Value, Error> {
+        return Ok(serde_json::to_value(self)?);
+    }
+
+}
+
+
+/// Creates a new directory in the backup directory, named after the current
+/// time and a label, and numbered if there already is one by that name
+pub fn new_backup_directory(label: &str) -> Result<PathBuf, Error> {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+    let mut backup_directory = get_backup_directory()?.join(format!("{}-{}", stamp, label));
+    let mut n = 1;
+    while backup_directory.exists() {
+        n += 1;
+        backup_directory = get_backup_directory()?.join(format!("{}-{}-{}", stamp, label, n));
+    }
+    make_directory(backup_directory.clone())?;
+    return Ok(backup_directory);
+}
+
+
+/**
+Moves the data of a backend that is no longer in use into its backup
+directory, so that nothing is ever deleted by a migration.
+*/
+fn retire(kind: StorageKind, backup_directory: PathBuf) -> Result<(), Error> {
+    let paths = match kind {
+        StorageKind::File => FileStorage::paths()?,
+        StorageKind::Sqlite => vec![SqliteStorage::path()?]
+    };
+    for path in paths.into_iter().filter(|p| p.exists()) {
+        let mut destination = backup_directory.clone();
+        destination.push(path.file_name().unwrap_or_default());
+        trace!("Moving {} to {}", path.to_string_lossy(), destination.to_string_lossy());
+        rename(path, destination)?;
+    }
+    info!("Previous {:?} storage moved to {}", kind, backup_directory.to_string_lossy());
+    return Ok(());
+}
+
+
+/**
+Copies every staged event, commit, project and the config from the backend in
+use to another one, checks that the copy is identical and only then switches
+the config over to the new backend.
+*/
+fn migrate(args: &cli::cli_storage::StorageMigrateCommand) -> Result<(), Error> {
+    let from = storage_kind()?;
+    if from == args.to {
+        return Err(Error::Storage(format!("Already using {:?} storage", from)));
+    }
+
+    let source = open_kind(from)?;
+    let snapshot = Snapshot::take(source.as_ref())?;
+    let mut config = match &snapshot.config {
+        Some(config) => config.clone(),
+        None => { return Err(Error::Storage("The config could not be read, fix it before migrating".to_string())); }
+    };
+
+    let target = open_kind(args.to)?;
+    if !Snapshot::take(target.as_ref())?.is_empty() {
+        return Err(Error::Storage(format!("{:?} storage already contains data, refusing to overwrite it", args.to)));
+    }
+
+    info!(
+        "Copying {} staged event(s), {} commit(s) and {} project(s) to {:?} storage",
+        snapshot.staged.len(), snapshot.history.len(), snapshot.projects.len(), args.to
+    );
+    for event in snapshot.staged.iter() {
+        target.stage_event(event)?;
+    }
+    // Commits are replayed oldest first so that every parent exists
+    for commit in snapshot.history.iter().rev() {
+        target.append_commit(commit)?;
+    }
+    for project in snapshot.projects.iter() {
+        target.save_project(project)?;
+    }
+
+    // The copy is compared with the original before anything is switched, so
+    // the copy does not yet claim to use the new backend
+    target.save_config(&config)?;
+    if Snapshot::take(target.as_ref())?.fingerprint()? != snapshot.fingerprint()? {
+        return Err(Error::Storage(format!("The copy in {:?} storage does not match the original, the migration was aborted", args.to)));
+    }
+
+    // The config file stays in place since it selects the backend, but a
+    // copy of it is kept alongside the data it pointed to
+    let backup_directory = new_backup_directory(&format!("{:?}", from).to_lowercase())?;
+    copy(get_config_path()?, backup_directory.join("config.yaml"))?;
+
+    // Switch over, and only then move the old data out of the way, so that
+    // the config file never points to a backend that is gone. The config file
+    // always records the backend in use.
+    config.storage = args.to;
+    target.save_config(&config)?;
+    if args.to == StorageKind::Sqlite {
+        write_sqlite_config_pointer()?;
+    }
+    retire(from, backup_directory)?;
+    info!("Migrated to {:?} storage", args.to);
+    return Ok(());
+}
+
+
+pub fn execute(args: &cli::cli_storage::StorageCommand) -> Result<(), Error> {
+    match &args.command {
+        cli::cli_storage::StorageSubcommand::Migrate(storage_sc) => {migrate(storage_sc)?;}
+    }
+    return Ok(());
+}
@@ -0,0 +1,258 @@
+/*!
+The original storage layout, as plain files in the Doubletime home directory:
+
+- `Staging/{timestamp}_{uuid}.json`, one file per staged event
+- `Ledger/{commit_id}.json`, one file per commit, and `Ledger/HEAD`
+- `Projects/{name}.yaml`, one file per project
+- `config.yaml`
+*/
+
+use std::fs::{read_dir, remove_file};
+use std::path::PathBuf;
+
+use log::{trace, debug};
+
+use crate::config::{Config, get_config_path, get_ledger_directory, get_project_directory, get_staging_directory};
+use crate::error::Error;
+use crate::event::Event;
+use crate::file_utils::{make_directory, read_file_to_string, write_string_to_file_atomically};
+use crate::ledger::Commit;
+use crate::project::{Project, validate_project_name};
+use crate::schema::{Record, SCHEMA_VERSION, from_json, from_yaml, json_version, to_json, to_json_pretty, to_yaml, yaml_version};
+use crate::storage::Storage;
+
+
+pub struct FileStorage {}
+
+
+impl FileStorage {
+
+    /// Opens the file storage, creating its directories if needed
+    pub fn open() -> Result<FileStorage, Error> {
+        for directory in FileStorage::paths()? {
+            make_directory(directory)?;
+        }
+        return Ok(FileStorage {});
+    }
+
+    /// Directories holding the data of the file storage
+    pub fn paths() -> Result<Vec<PathBuf>, Error> {
+        return Ok(vec![get_staging_directory()?, get_ledger_directory()?, get_project_directory()?]);
+    }
+
+    /// Lists the files of a directory with an extension, which leaves out
+    /// temporary files
+    fn files_with_extension(&self, directory: PathBuf, extension: &str) -> Result<Vec<PathBuf>, Error> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == extension) {
+                paths.push(path);
+            }
+        }
+        return Ok(paths);
+    }
+
+    /// Loads every staged event alongside the path of the file it is in
+    fn load_staged_event_files(&self) -> Result<Vec<(PathBuf, Event)>, Error> {
+        let staging_directory = get_staging_directory()?;
+        trace!("Loading staged events from {}", staging_directory.to_string_lossy());
+
+        let mut events: Vec<(PathBuf, Event)> = Vec::new();
+        for path in self.files_with_extension(staging_directory, "json")? {
+            let content = read_file_to_string(path.clone())?;
+            let event: Event = from_json(&content, Record::Event)?;
+            events.push((path, event));
+        }
+        return Ok(events);
+    }
+
+    /// Gets the path of the HEAD file
+    fn head_path(&self) -> Result<PathBuf, Error> {
+        let mut file_path = get_ledger_directory()?;
+        file_path.push("HEAD");
+        return Ok(file_path);
+    }
+
+    /// Gets the path of the file storing a commit
+    fn commit_path(&self, commit_id: &str) -> Result<PathBuf, Error> {
+        let mut file_path = get_ledger_directory()?;
+        file_path.push(format!("{}.json", commit_id));
+        return Ok(file_path);
+    }
+
+    /// Gets the path of the yaml file storing a project
+    fn project_path(&self, name: &str) -> Result<PathBuf, Error> {
+        validate_project_name(name)?;
+        let mut file_path = get_project_directory()?;
+        file_path.push(format!("{}.yaml", name));
+        return Ok(file_path);
+    }
+
+    /**
+    Rewrites a file in the current schema version if it was written in an
+    older one, returning whether it was (or with dry_run, would be) rewritten.
+    */
+    fn upgrade_file<T>(
+        &self, path: PathBuf, record: Record, version: fn(&str) -> Result<u32, Error>,
+        read: fn(&str, Record) -> Result<T, Error>, write: fn(&T) -> Result<String, Error>, dry_run: bool
+    ) -> Result<bool, Error> {
+        let content = read_file_to_string(path.clone())?;
+        if version(&content)? >= SCHEMA_VERSION {
+            return Ok(false);
+        }
+        if dry_run {
+            return Ok(true);
+        }
+        debug!("Upgrading {}", path.to_string_lossy());
+        let upgraded = read(&content, record)?;
+        write_string_to_file_atomically(&write(&upgraded)?, path)?;
+        return Ok(true);
+    }
+
+    /// Reads and parses a project yaml file
+    fn read_project_file(&self, path: PathBuf) -> Result<Project, Error> {
+        let content = read_file_to_string(path.clone())?;
+        match from_yaml(&content, Record::Project) {
+            Ok(project) => { return Ok(project); },
+            Err(e) => {
+                return Err(Error::Project(format!("Project file {} is invalid: {}", path.to_string_lossy(), e)));
+            }
+        }
+    }
+
+}
+
+
+impl Storage for FileStorage {
+
+    fn load_staged_events(&self) -> Result<Vec<Event>, Error> {
+        let events = self.load_staged_event_files()?;
+        return Ok(events.into_iter().map(|(_, event)| event).collect());
+    }
+
+    fn stage_event(&self, event: &Event) -> Result<(), Error> {
+        // Several events can be staged within the same second (e.g. when
+        // importing), so the uuid is part of the name
+        let mut file_path = get_staging_directory()?;
+        file_path.push(format!("{}_{}.json", event.timestamp, event.uuid));
+        if file_path.exists() {
+            return Err(Error::Storage(format!("Staged event file {} already exists", file_path.to_string_lossy())));
+        }
+        debug!("Staging event to {}", file_path.to_string_lossy());
+        write_string_to_file_atomically(&to_json(event)?, file_path)?;
+        return Ok(());
+    }
+
+    fn unstage_event(&self, uuid: &str) -> Result<(), Error> {
+        // Files staged by older versions are not named after the uuid, so the
+        // file is found by its content
+        for (path, event) in self.load_staged_event_files()? {
+            if event.uuid == uuid {
+                debug!("Removing staged file {}", path.to_string_lossy());
+                remove_file(path)?;
+                return Ok(());
+            }
+        }
+        return Err(Error::Storage(format!("No staged event with id {}", uuid)));
+    }
+
+    fn read_head(&self) -> Result<Option<String>, Error> {
+        let head_path = self.head_path()?;
+        if !head_path.exists() {
+            trace!("No HEAD file at {}, nothing committed yet", head_path.to_string_lossy());
+            return Ok(None);
+        }
+        let head = read_file_to_string(head_path)?;
+        let head = head.trim();
+        if head.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(head.to_string()));
+    }
+
+    fn read_commit(&self, commit_id: &str) -> Result<Commit, Error> {
+        let commit_path = self.commit_path(commit_id)?;
+        trace!("Reading commit from {}", commit_path.to_string_lossy());
+        let content = read_file_to_string(commit_path)?;
+        let commit: Commit = from_json(&content, Record::Commit)?;
+        return Ok(commit);
+    }
+
+    /// The commit file is written first and HEAD is only moved once that
+    /// succeeded, both via atomic renames
+    fn append_commit(&self, commit: &Commit) -> Result<(), Error> {
+        let commit_path = self.commit_path(&commit.commit_id)?;
+        if commit_path.exists() {
+            return Err(Error::Storage(format!("Commit file {} already exists", commit_path.to_string_lossy())));
+        }
+        debug!("Writing commit {} to {}", commit.commit_id, commit_path.to_string_lossy());
+        let commit_as_str = to_json_pretty(commit)?;
+        write_string_to_file_atomically(&commit_as_str, commit_path)?;
+
+        debug!("Moving HEAD to {}", commit.commit_id);
+        write_string_to_file_atomically(&commit.commit_id, self.head_path()?)?;
+        return Ok(());
+    }
+
+    fn load_projects(&self) -> Result<Vec<Project>, Error> {
+        let mut projects: Vec<Project> = Vec::new();
+        for path in self.files_with_extension(get_project_directory()?, "yaml")? {
+            projects.push(self.read_project_file(path)?);
+        }
+        projects.sort_by(|a, b| a.name.cmp(&b.name));
+        return Ok(projects);
+    }
+
+    fn load_project(&self, name: &str) -> Result<Option<Project>, Error> {
+        let project_path = self.project_path(name)?;
+        if !project_path.exists() {
+            trace!("No project file at {}", project_path.to_string_lossy());
+            return Ok(None);
+        }
+        return Ok(Some(self.read_project_file(project_path)?));
+    }
+
+    fn save_project(&self, project: &Project) -> Result<(), Error> {
+        let project_path = self.project_path(&project.name)?;
+        write_string_to_file_atomically(&to_yaml(project)?, project_path)?;
+        return Ok(());
+    }
+
+    fn remove_project(&self, name: &str) -> Result<(), Error> {
+        remove_file(self.project_path(name)?)?;
+        return Ok(());
+    }
+
+    fn load_config(&self) -> Result<Config, Error> {
+        let config_path = get_config_path()?;
+        let config_as_str = read_file_to_string(config_path.clone())?;
+        match from_yaml(&config_as_str, Record::Config) {
+            Ok(config) => { return Ok(config); },
+            Err(e) => {
+                return Err(Error::Config(format!("Config file {} is invalid: {}", config_path.to_string_lossy(), e)));
+            }
+        }
+    }
+
+    fn save_config(&self, config: &Config) -> Result<(), Error> {
+        write_string_to_file_atomically(&to_yaml(config)?, get_config_path()?)?;
+        return Ok(());
+    }
+
+    fn upgrade(&self, dry_run: bool) -> Result<usize, Error> {
+        let mut n_upgraded = 0;
+        for path in self.files_with_extension(get_staging_directory()?, "json")? {
+            n_upgraded += self.upgrade_file(path, Record::Event, json_version, from_json::<Event>, to_json, dry_run)? as usize;
+        }
+        for path in self.files_with_extension(get_ledger_directory()?, "json")? {
+            n_upgraded += self.upgrade_file(path, Record::Commit, json_version, from_json::<Commit>, to_json_pretty, dry_run)? as usize;
+        }
+        for path in self.files_with_extension(get_project_directory()?, "yaml")? {
+            n_upgraded += self.upgrade_file(path, Record::Project, yaml_version, from_yaml::<Project>, to_yaml, dry_run)? as usize;
+        }
+        n_upgraded += self.upgrade_file(get_config_path()?, Record::Config, yaml_version, from_yaml::<Config>, to_yaml, dry_run)? as usize;
+        return Ok(n_upgraded);
+    }
+
+}
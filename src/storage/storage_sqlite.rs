@@ -0,0 +1,286 @@
+/*!
+Storage in a single embedded SQLite database, `doubletime.sqlite` in the
+Doubletime home directory. Every record is stored in full as json, next to a
+few plain columns (dates, projects, hours) that make the database pleasant to
+query by hand.
+*/
+
+use std::path::PathBuf;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use log::{trace, debug};
+
+use crate::config::{Config, get_database_path};
+use crate::error::Error;
+use crate::event::Event;
+use crate::ledger::Commit;
+use crate::project::Project;
+use crate::schema::{Record, SCHEMA_VERSION, from_json, json_version, to_json};
+use crate::storage::Storage;
+
+
+/// Tables of the database, created when it is first opened
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS staged_events (
+    uuid TEXT PRIMARY KEY,
+    date TEXT NOT NULL,
+    project TEXT NOT NULL,
+    hours REAL NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS commits (
+    commit_id TEXT PRIMARY KEY,
+    parent TEXT REFERENCES commits (commit_id),
+    message TEXT NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS committed_events (
+    commit_id TEXT NOT NULL REFERENCES commits (commit_id),
+    position INTEGER NOT NULL,
+    uuid TEXT NOT NULL,
+    date TEXT NOT NULL,
+    project TEXT NOT NULL,
+    hours REAL NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (commit_id, position)
+);
+CREATE TABLE IF NOT EXISTS head (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    commit_id TEXT NOT NULL REFERENCES commits (commit_id)
+);
+CREATE TABLE IF NOT EXISTS projects (
+    name TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    data TEXT NOT NULL
+);
+";
+
+
+pub struct SqliteStorage {
+    connection: Connection
+}
+
+
+impl SqliteStorage {
+
+    /// Opens the database, creating it and its tables if needed
+    pub fn open() -> Result<SqliteStorage, Error> {
+        let path = SqliteStorage::path()?;
+        trace!("Opening SQLite database at {}", path.to_string_lossy());
+        let connection = Connection::open(path)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+
+        // The version of the database is that of its tables, the records in
+        // them carry their own
+        let version: u32 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(Error::Storage(format!(
+                "The database was written by a newer version of Doubletime (schema version {}, this version reads up to {}), upgrade dt",
+                version, SCHEMA_VERSION
+            )));
+        }
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch(&format!("PRAGMA user_version = {};", SCHEMA_VERSION))?;
+        return Ok(SqliteStorage { connection });
+    }
+
+    /// Path of the database file
+    pub fn path() -> Result<PathBuf, Error> {
+        return get_database_path();
+    }
+
+    /**
+    Rewrites the records of a table written in an older schema version in
+    the current one, returning how many were (or with dry_run, would be)
+    rewritten.
+    */
+    fn upgrade_table<T: Serialize + DeserializeOwned>(&self, table: &str, record: Record, dry_run: bool) -> Result<usize, Error> {
+        let mut statement = self.connection.prepare(&format!("SELECT rowid, data FROM {}", table))?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        let mut n_upgraded = 0;
+        for row in rows {
+            let (rowid, data) = row?;
+            if json_version(&data)? >= SCHEMA_VERSION {
+                continue;
+            }
+            n_upgraded += 1;
+            if dry_run {
+                continue;
+            }
+            debug!("Upgrading row {} of {}", rowid, table);
+            let upgraded: T = from_json(&data, record)?;
+            self.connection.execute(
+                &format!("UPDATE {} SET data = ?1 WHERE rowid = ?2", table),
+                params![to_json(&upgraded)?, rowid]
+            )?;
+        }
+        return Ok(n_upgraded);
+    }
+
+}
+
+
+impl Storage for SqliteStorage {
+
+    fn load_staged_events(&self) -> Result<Vec<Event>, Error> {
+        let mut statement = self.connection.prepare("SELECT data FROM staged_events")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut events: Vec<Event> = Vec::new();
+        for data in rows {
+            events.push(from_json(&data?, Record::Event)?);
+        }
+        return Ok(events);
+    }
+
+    fn stage_event(&self, event: &Event) -> Result<(), Error> {
+        debug!("Staging event {} in the database", event.uuid);
+        self.connection.execute(
+            "INSERT INTO staged_events (uuid, date, project, hours, data) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![event.uuid, event.now_str, event.project, event.hours, to_json(event)?]
+        )?;
+        return Ok(());
+    }
+
+    fn unstage_event(&self, uuid: &str) -> Result<(), Error> {
+        let removed = self.connection.execute("DELETE FROM staged_events WHERE uuid = ?1", params![uuid])?;
+        if removed == 0 {
+            return Err(Error::Storage(format!("No staged event with id {}", uuid)));
+        }
+        return Ok(());
+    }
+
+    fn read_head(&self) -> Result<Option<String>, Error> {
+        let head = self.connection
+            .query_row("SELECT commit_id FROM head WHERE id = 0", [], |row| row.get::<_, String>(0))
+            .optional()?;
+        return Ok(head);
+    }
+
+    fn read_commit(&self, commit_id: &str) -> Result<Commit, Error> {
+        let commit = self.connection.query_row(
+            "SELECT parent, message, timestamp FROM commits WHERE commit_id = ?1",
+            params![commit_id],
+            |row| Ok(Commit {
+                commit_id: commit_id.to_string(),
+                parent: row.get(0)?,
+                message: row.get(1)?,
+                timestamp: row.get(2)?,
+                events: Vec::new()
+            })
+        ).optional()?;
+        let mut commit = match commit {
+            Some(commit) => commit,
+            None => { return Err(Error::Storage(format!("Commit {} does not exist", commit_id))); }
+        };
+
+        let mut statement = self.connection.prepare(
+            "SELECT data FROM committed_events WHERE commit_id = ?1 ORDER BY position"
+        )?;
+        let rows = statement.query_map(params![commit_id], |row| row.get::<_, String>(0))?;
+        for data in rows {
+            commit.events.push(from_json(&data?, Record::Event)?);
+        }
+        return Ok(commit);
+    }
+
+    /// The commit, its events and HEAD are written in a single transaction
+    fn append_commit(&self, commit: &Commit) -> Result<(), Error> {
+        debug!("Writing commit {} to the database", commit.commit_id);
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute(
+            "INSERT INTO commits (commit_id, parent, message, timestamp) VALUES (?1, ?2, ?3, ?4)",
+            params![commit.commit_id, commit.parent, commit.message, commit.timestamp]
+        )?;
+        for (position, event) in commit.events.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO committed_events (commit_id, position, uuid, date, project, hours, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    commit.commit_id, position as i64, event.uuid, event.now_str, event.project, event.hours,
+                    to_json(event)?
+                ]
+            )?;
+        }
+        transaction.execute(
+            "INSERT INTO head (id, commit_id) VALUES (0, ?1) ON CONFLICT (id) DO UPDATE SET commit_id = excluded.commit_id",
+            params![commit.commit_id]
+        )?;
+        transaction.commit()?;
+        return Ok(());
+    }
+
+    fn load_projects(&self) -> Result<Vec<Project>, Error> {
+        let mut statement = self.connection.prepare("SELECT data FROM projects ORDER BY name")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut projects: Vec<Project> = Vec::new();
+        for data in rows {
+            projects.push(from_json(&data?, Record::Project)?);
+        }
+        return Ok(projects);
+    }
+
+    fn load_project(&self, name: &str) -> Result<Option<Project>, Error> {
+        let data = self.connection
+            .query_row("SELECT data FROM projects WHERE name = ?1", params![name], |row| row.get::<_, String>(0))
+            .optional()?;
+        match data {
+            Some(data) => { return Ok(Some(from_json(&data, Record::Project)?)); },
+            None => { return Ok(None); }
+        }
+    }
+
+    fn save_project(&self, project: &Project) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO projects (name, data) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET data = excluded.data",
+            params![project.name, to_json(project)?]
+        )?;
+        return Ok(());
+    }
+
+    fn remove_project(&self, name: &str) -> Result<(), Error> {
+        self.connection.execute("DELETE FROM projects WHERE name = ?1", params![name])?;
+        return Ok(());
+    }
+
+    fn load_config(&self) -> Result<Config, Error> {
+        let data = self.connection
+            .query_row("SELECT data FROM config WHERE id = 0", [], |row| row.get::<_, String>(0))
+            .optional()?;
+        match data {
+            Some(data) => { return from_json(&data, Record::Config); },
+            None => { return Err(Error::Storage("No config is stored in the database".to_string())); }
+        }
+    }
+
+    fn save_config(&self, config: &Config) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO config (id, data) VALUES (0, ?1) ON CONFLICT (id) DO UPDATE SET data = excluded.data",
+            params![to_json(config)?]
+        )?;
+        return Ok(());
+    }
+
+    /// Every table is rewritten in a single transaction
+    fn upgrade(&self, dry_run: bool) -> Result<usize, Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let n_upgraded = self.upgrade_table::<Event>("staged_events", Record::Event, dry_run)?
+            + self.upgrade_table::<Event>("committed_events", Record::Event, dry_run)?
+            + self.upgrade_table::<Project>("projects", Record::Project, dry_run)?
+            + self.upgrade_table::<Config>("config", Record::Config, dry_run)?;
+        if !dry_run {
+            // The date columns mirror the dates of the events, whose format
+            // may have changed with them
+            for table in ["staged_events", "committed_events"] {
+                self.connection.execute(&format!("UPDATE {} SET date = json_extract(data, '$.now_str')", table), [])?;
+            }
+        }
+        transaction.commit()?;
+        return Ok(n_upgraded);
+    }
+
+}
@@ -0,0 +1,260 @@
+/*!
+Live timers that record time as it happens. Running timers are persisted to
+disk in the Doubletime home directory, so they survive the shell exiting or
+the computer rebooting. Stopping a timer stages a regular event.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::fs::remove_file;
+use log::{trace, info, warn};
+
+use crate::cli;
+use crate::config::get_timer_path;
+use crate::datetime::{get_current_timestamp, format_date, format_stored_date, format_timestamp, home_timezone, parse_stored_date, today_in};
+use crate::error::Error;
+use crate::event::stage_new_event;
+use crate::file_utils::{read_file_to_string, write_string_to_file_atomically};
+use crate::project::load_projects;
+use crate::safeties::{check_project, enforce};
+use crate::schema::{Record, SCHEMA_VERSION, from_json, json_version, to_json_pretty};
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timer {
+    pub project: String,
+    pub message: String,
+
+    /// Date the timer was started on, which the staged event is filed under
+    pub now_str: String,
+
+    /// Timestamp/epoch at which the timer was first started
+    pub started: i64,
+
+    /// Seconds accumulated by the timer before it was last paused
+    pub elapsed_seconds: i64,
+
+    /// Timestamp/epoch at which the timer was last (re)started, None while
+    /// the timer is paused
+    pub running_since: Option<i64>
+}
+
+
+/// Contents of the file persisting the running timers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TimerFile {
+    pub timers: Vec<Timer>
+}
+
+
+impl Timer {
+
+    /// Total number of seconds the timer has been running for
+    fn elapsed(&self, now: i64) -> i64 {
+        let current_segment = self.running_since.map_or(0, |since| now - since);
+        return self.elapsed_seconds + current_segment;
+    }
+
+    /// Elapsed time in hours, rounded to the nearest minute
+    fn elapsed_hours(&self, now: i64) -> f32 {
+        let minutes = (self.elapsed(now) as f32 / 60.0).round();
+        return minutes / 60.0;
+    }
+
+}
+
+
+/// Loads the running timers from disk
+fn load_timers() -> Result<Vec<Timer>, Error> {
+    let timer_path = get_timer_path()?;
+    if !timer_path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = read_file_to_string(timer_path)?;
+    let timer_file: TimerFile = from_json(&content, Record::Timers)?;
+    trace!("Loaded timers {:?}", timer_file.timers);
+    return Ok(timer_file.timers);
+}
+
+
+/// Saves the running timers to disk, removing the file once none are left
+fn save_timers(timers: &[Timer]) -> Result<(), Error> {
+    let timer_path = get_timer_path()?;
+    if timers.is_empty() {
+        if timer_path.exists() {
+            remove_file(timer_path)?;
+        }
+        return Ok(());
+    }
+    let timers_as_str = to_json_pretty(&TimerFile { timers: timers.to_vec() })?;
+    write_string_to_file_atomically(&timers_as_str, timer_path)?;
+    return Ok(());
+}
+
+
+/// Rewrites the timer file in the current schema version if it was written in
+/// an older one, returning whether it was (or with dry_run, would be) rewritten
+pub fn upgrade_timer_file(dry_run: bool) -> Result<bool, Error> {
+    let timer_path = get_timer_path()?;
+    if !timer_path.exists() || json_version(&read_file_to_string(timer_path)?)? >= SCHEMA_VERSION {
+        return Ok(false);
+    }
+    if !dry_run {
+        save_timers(&load_timers()?)?;
+    }
+    return Ok(true);
+}
+
+
+/// Finds the index of the timer to operate on. The project only needs to be
+/// provided when several timers are running at once.
+fn select_timer(timers: &[Timer], project: &Option<String>) -> Result<usize, Error> {
+    if timers.is_empty() {
+        return Err(Error::Validation("No timer is running, start one with `dt start <project>`".to_string()));
+    }
+    match project {
+        Some(project) => {
+            match timers.iter().position(|t| &t.project == project) {
+                Some(index) => { return Ok(index); },
+                None => { return Err(Error::Validation(format!("No timer is running for project {}", project))); }
+            }
+        },
+        None => {
+            if timers.len() > 1 {
+                let projects: Vec<&str> = timers.iter().map(|t| t.project.as_str()).collect();
+                return Err(Error::Validation(format!("Several timers are running ({}), select one with --project", projects.join(", "))));
+            }
+            return Ok(0);
+        }
+    }
+}
+
+
+fn start(args: &cli::cli_timer::TimerStartCommand, safeties_on: bool) -> Result<(), Error> {
+    let mut timers = load_timers()?;
+    if timers.iter().any(|t| t.project == args.project) {
+        return Err(Error::Validation(format!("A timer is already running for project {}", args.project)));
+    }
+    if !timers.is_empty() {
+        let projects: Vec<&str> = timers.iter().map(|t| t.project.as_str()).collect();
+        enforce(Err(format!("A timer is already running ({})", projects.join(", "))), safeties_on)?;
+    }
+
+    // Catch problems with the project now rather than when the timer stops
+    let projects = load_projects()?;
+    enforce(check_project(&args.project, &projects), safeties_on)?;
+
+    let now = get_current_timestamp();
+    let timer = Timer {
+        project: args.project.clone(),
+        message: args.message.clone().unwrap_or("NULL".to_string()),
+        now_str: format_stored_date(today_in(home_timezone())),
+        started: now,
+        elapsed_seconds: 0,
+        running_since: Some(now)
+    };
+    timers.push(timer);
+    save_timers(&timers)?;
+    info!("Timer started for project {}", args.project);
+    return Ok(());
+}
+
+
+fn stop(args: &cli::cli_timer::TimerStopCommand, safeties_on: bool) -> Result<(), Error> {
+    let mut timers = load_timers()?;
+    let index = select_timer(&timers, &args.project)?;
+    let timer = timers[index].clone();
+
+    let hours = timer.elapsed_hours(get_current_timestamp());
+    if hours <= 0.0 {
+        warn!("Timer for project {} ran for less than a minute, nothing staged", timer.project);
+    } else {
+        // The timer is only discarded once its event is safely staged
+        let now = parse_stored_date(&timer.now_str)?;
+        stage_new_event(now, hours, timer.project.clone(), timer.message.clone(), safeties_on)?;
+
+        // How the hours were spread across the days is not known, so they
+        // are left for the user to split
+        if today_in(home_timezone()) > now {
+            warn!(
+                "Timer for project {} ran past midnight, all of its {:.2} hours are filed under {}. Unstage the event and add it with --from, --to and --spread to split them across the days",
+                timer.project, hours, format_date(now)
+            );
+        }
+    }
+
+    timers.remove(index);
+    save_timers(&timers)?;
+    info!("Timer stopped for project {}", timer.project);
+    return Ok(());
+}
+
+
+fn pause(args: &cli::cli_timer::TimerPauseCommand) -> Result<(), Error> {
+    let mut timers = load_timers()?;
+    let index = select_timer(&timers, &args.project)?;
+    let now = get_current_timestamp();
+    let timer = &mut timers[index];
+    match timer.running_since {
+        Some(since) => {
+            timer.elapsed_seconds += now - since;
+            timer.running_since = None;
+        },
+        None => {
+            return Err(Error::Validation(format!("Timer for project {} is already paused", timer.project)));
+        }
+    }
+    info!("Timer paused for project {}", timer.project);
+    save_timers(&timers)?;
+    return Ok(());
+}
+
+
+fn resume(args: &cli::cli_timer::TimerResumeCommand) -> Result<(), Error> {
+    let mut timers = load_timers()?;
+    let index = select_timer(&timers, &args.project)?;
+    let timer = &mut timers[index];
+    if timer.running_since.is_some() {
+        return Err(Error::Validation(format!("Timer for project {} is not paused", timer.project)));
+    }
+    timer.running_since = Some(get_current_timestamp());
+    info!("Timer resumed for project {}", timer.project);
+    save_timers(&timers)?;
+    return Ok(());
+}
+
+
+fn current() -> Result<(), Error> {
+    let timers = load_timers()?;
+    if timers.is_empty() {
+        info!("No timer is running");
+        return Ok(());
+    }
+    let now = get_current_timestamp();
+    for timer in timers.iter() {
+        let elapsed = timer.elapsed(now);
+        let state = match timer.running_since {
+            Some(_) => "running",
+            None => "paused"
+        };
+        println!("{} ({})", timer.project, state);
+        println!("| started     {}", format_timestamp(timer.started));
+        println!("| elapsed     {}h{:02}m", elapsed / 3600, (elapsed % 3600) / 60);
+        println!("| message     {:?}", timer.message);
+    }
+    return Ok(());
+}
+
+
+pub fn execute(command: &cli::Command, safeties_on: bool) -> Result<(), Error> {
+    match command {
+        cli::Command::Start(timer_sc) => {start(timer_sc, safeties_on)?;},
+        cli::Command::Stop(timer_sc) => {stop(timer_sc, safeties_on)?;},
+        cli::Command::Pause(timer_sc) => {pause(timer_sc)?;},
+        cli::Command::Resume(timer_sc) => {resume(timer_sc)?;},
+        cli::Command::Current(_) => {current()?;},
+        _ => {
+            trace!("Not a timer command: {:?}", command);
+        }
+    }
+    return Ok(());
+}